@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::config::{BackendConfig, Config, Error, LossConfig, NodeConfig, ShutdownConfig};
+use crate::fixture;
+
+use cbmix_dmx::{MAX_PRIORITY, MAX_UNIVERSE};
+use serde::Deserialize;
+use toml::{Spanned, Value};
+
+// a mistake in one field of a config section, like `node.sum` and `a`
+struct Problem {
+    section: &'static str,
+    key: String,
+    field: &'static str,
+    message: String,
+}
+
+// where each field was written, parsed loosely so it only fails where the
+// config itself would
+#[derive(Deserialize, Default)]
+struct Spans {
+    #[serde(default)]
+    input: HashMap<String, HashMap<String, Spanned<Value>>>,
+    #[serde(default)]
+    node: HashMap<String, HashMap<String, Spanned<Value>>>,
+    #[serde(default)]
+    output: HashMap<String, HashMap<String, Spanned<Value>>>,
+    #[serde(default)]
+    record: HashMap<String, Spanned<Value>>,
+    #[serde(default)]
+    profile: HashMap<String, HashMap<String, Spanned<Value>>>,
+    #[serde(default)]
+    fixture: HashMap<String, HashMap<String, Spanned<Value>>>,
+}
+
+// report every problem with the config at `path`, returning whether there
+// were none. nothing is connected to or bound
+pub fn check(path: &Path) -> bool {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("error: unable to read {}: {}", path.display(), e);
+            return false;
+        }
+    };
+
+    let mut diagnostics = match toml::from_str::<Config>(&text) {
+        Ok(config) => {
+            let spans: Spans = toml::from_str(&text).unwrap_or_default();
+            validate(config)
+                .into_iter()
+                .map(|problem| (spans.find(&problem), problem.message))
+                .collect()
+        }
+        Err(e) => vec![(e.span(), e.message().to_string())],
+    };
+    diagnostics.sort_by_key(|(span, _)| span.as_ref().map(|s| s.start));
+
+    for (span, message) in &diagnostics {
+        eprintln!("error: {}", message);
+        match span {
+            Some(span) => eprintln!("{}", snippet(path, &text, span.clone())),
+            None => eprintln!(" --> {}\n", path.display()),
+        }
+    }
+    if diagnostics.is_empty() {
+        println!("{} is valid", path.display());
+    }
+
+    diagnostics.is_empty()
+}
+
+fn validate(mut config: Config) -> Vec<Problem> {
+    // the rest only makes sense with the fixtures resolved to plain channels
+    if let Err(e) = fixture::resolve(&mut config) {
+        return vec![fixture_problem(&config, e)];
+    }
+
+    let mut problems = Vec::new();
+    let mut problem = |section, key: &str, field, message| {
+        problems.push(Problem {
+            section,
+            key: key.to_string(),
+            field,
+            message,
+        })
+    };
+
+    let nodes: HashMap<&str, &NodeConfig> =
+        config.node.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let exists = |key: &str| nodes.contains_key(key) || config.input.iter().any(|(k, _)| k == key);
+    let not_static = |key: &str| match nodes.get(key) {
+        Some(NodeConfig::Static { .. }) => None,
+        Some(_) => Some(format!("node {} is not a static node", key)),
+        None => Some(format!("node {} does not exist", key)),
+    };
+
+    let mut universes = HashMap::new();
+    for (key, input) in &config.input {
+        match universes.get(&input.universe) {
+            Some(other) => problem(
+                "input",
+                key,
+                "universe",
+                format!(
+                    "universe {} is already used by input {}",
+                    input.universe, other
+                ),
+            ),
+            None => {
+                universes.insert(input.universe, key);
+            }
+        }
+
+        match &input.on_loss {
+            LossConfig::Universe(universe) if *universe == input.universe => problem(
+                "input",
+                key,
+                "on_loss",
+                format!("input {} can't fall back to its own universe", key),
+            ),
+            LossConfig::Node(node) => {
+                if let Some(message) = not_static(node) {
+                    problem("input", key, "on_loss", message);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut missing = false;
+    for (key, node) in &config.node {
+        for (field, reference) in node.references() {
+            if !exists(reference) {
+                missing = true;
+                problem(
+                    "node",
+                    key,
+                    field,
+                    format!(
+                        "node {} reads from {}, which is not a node or input",
+                        key, reference
+                    ),
+                );
+            }
+        }
+
+        match node {
+            NodeConfig::Rewire { map, .. } if map.len() != 512 => problem(
+                "node",
+                key,
+                "map",
+                format!("rewire map has {} channels, it needs 512", map.len()),
+            ),
+            NodeConfig::Rewire { map, .. } => {
+                if let Some(channel) = map.iter().find(|c| **c >= 512) {
+                    problem(
+                        "node",
+                        key,
+                        "map",
+                        format!("rewire map channel {} is not from 0 to 511", channel),
+                    );
+                }
+            }
+            NodeConfig::Playback { file, .. } if !file.exists() => problem(
+                "node",
+                key,
+                "file",
+                format!("recording {} does not exist", file.display()),
+            ),
+            _ => {}
+        }
+    }
+
+    // a cycle only makes sense to look for once every reference resolves
+    if !missing {
+        if let Err(Error::Cycle(cycle)) = config.clone().sort_nodes() {
+            let field = nodes[cycle[0].as_str()]
+                .references()
+                .find(|(_, reference)| *reference == cycle[1])
+                .map_or("", |(field, _)| field);
+            problem(
+                "node",
+                &cycle[0],
+                field,
+                format!(
+                    "nodes read from each other in a cycle: {}",
+                    cycle.join(" -> ")
+                ),
+            );
+        }
+    }
+
+    let mut sent: Vec<(u32, &BackendConfig, &str)> = Vec::new();
+    for (key, output) in &config.output {
+        if !exists(&output.from) {
+            problem(
+                "output",
+                key,
+                "from",
+                format!(
+                    "output {} sends {}, which is not a node or input",
+                    key, output.from
+                ),
+            );
+        }
+
+        for backend in &output.backend {
+            if let BackendConfig::Sacn { priority, .. } = backend {
+                if let Some(universe) = output
+                    .universe
+                    .iter()
+                    .find(|u| !(1..=MAX_UNIVERSE).contains(*u))
+                {
+                    problem(
+                        "output",
+                        key,
+                        "universe",
+                        format!(
+                            "universe {} is out of range for sACN, 1 to {}",
+                            universe, MAX_UNIVERSE
+                        ),
+                    );
+                }
+                if let Some(priority) = priority.filter(|p| *p > MAX_PRIORITY) {
+                    problem(
+                        "output",
+                        key,
+                        "backend",
+                        format!(
+                            "sACN priority {} is above the maximum of {}",
+                            priority, MAX_PRIORITY
+                        ),
+                    );
+                }
+            }
+        }
+
+        for universe in &output.universe {
+            for backend in &output.backend {
+                let other = sent.iter().find(|(u, b, _)| u == universe && *b == backend);
+                match other {
+                    Some((_, _, other)) => problem(
+                        "output",
+                        key,
+                        "universe",
+                        format!("universe {} is already sent by output {}", universe, other),
+                    ),
+                    None => sent.push((*universe, backend, key)),
+                }
+            }
+        }
+
+        if let ShutdownConfig::Node(node) = &output.on_shutdown {
+            if let Some(message) = not_static(node) {
+                problem("output", key, "on_shutdown", message);
+            }
+        }
+        if let Some(fade) = output
+            .shutdown_fade
+            .filter(|fade| *fade >= config.shutdown_grace_period)
+        {
+            problem(
+                "output",
+                key,
+                "shutdown_fade",
+                format!(
+                    "output {} fades for {:?} on shutdown, which doesn't fit in the {:?} grace period",
+                    key, fade, config.shutdown_grace_period
+                ),
+            );
+        }
+    }
+
+    if let Some(record) = &config.record {
+        for node in record.nodes.iter().filter(|node| !exists(node)) {
+            problem(
+                "record",
+                "",
+                "nodes",
+                format!("recorded node {} is not a node or input", node),
+            );
+        }
+    }
+
+    problems
+}
+
+fn fixture_problem(config: &Config, error: Error) -> Problem {
+    // in the same case as the rest of the messages here
+    let mut message = error.to_string();
+    message[..1].make_ascii_lowercase();
+    let (section, key, field) = match error {
+        Error::UnknownProfile { fixture, .. } => ("fixture", fixture, "profile"),
+        Error::FixtureAddress { fixture, .. } => ("fixture", fixture, "address"),
+        Error::ChannelBits { profile, .. } | Error::DuplicateChannel { profile, .. } => {
+            ("profile", profile, "channels")
+        }
+        Error::UnknownAttribute { node, .. }
+        | Error::LevelRange { node, .. }
+        | Error::PatchWidth { node, .. }
+        | Error::PatchChannel { node, .. } => {
+            let field = match config.node.iter().find(|(key, _)| *key == node) {
+                Some((_, NodeConfig::Static { .. })) => "levels",
+                _ => "patch",
+            };
+            ("node", node, field)
+        }
+        _ => ("", String::new(), ""),
+    };
+
+    Problem {
+        section,
+        key,
+        field,
+        message,
+    }
+}
+
+impl Spans {
+    fn find(&self, problem: &Problem) -> Option<Range<usize>> {
+        let fields = match problem.section {
+            "input" => self.input.get(&problem.key)?,
+            "node" => self.node.get(&problem.key)?,
+            "output" => self.output.get(&problem.key)?,
+            "profile" => self.profile.get(&problem.key)?,
+            "fixture" => self.fixture.get(&problem.key)?,
+            "record" => &self.record,
+            _ => return None,
+        };
+
+        Some(fields.get(problem.field)?.span())
+    }
+}
+
+// the line holding `span`, underlined, in the style of compiler errors
+fn snippet(path: &Path, text: &str, span: Range<usize>) -> String {
+    let start = text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[span.start..]
+        .find('\n')
+        .map_or(text.len(), |i| span.start + i);
+    let line = text[..span.start].matches('\n').count() + 1;
+    let column = text[start..span.start].chars().count() + 1;
+    let width = text[span.start..span.end.min(end)].chars().count().max(1);
+
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{gutter}--> {}:{}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}\n",
+        path.display(),
+        line,
+        column,
+        line,
+        &text[start..end],
+        " ".repeat(column - 1),
+        "^".repeat(width),
+    )
+}
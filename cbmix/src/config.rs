@@ -1,9 +1,15 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs::read_to_string;
+use std::io;
 use std::marker::PhantomData;
-use std::path::Path;
+use std::mem::take;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use crate::fixture;
+
 use cbmix_admin::config::AdminConfig;
 use ola::DmxBuffer;
 use regex::Regex;
@@ -14,11 +20,53 @@ use serde::{
 use thiserror::Error;
 
 pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+pub const DEFAULT_INPUT_TIMEOUT: Duration = Duration::from_secs(3);
 
-#[derive(Error, Clone, Debug)]
+#[derive(Error, Debug)]
 pub enum Error {
     #[error("Error loading config: {0}")]
     LoadError(#[from] toml::de::Error),
+    #[error("Error reading config {}: {1}", .0.display())]
+    ReadError(PathBuf, io::Error),
+    #[error("Node {node} reads from {reference}, which is not a node or input")]
+    UnknownReference { node: String, reference: String },
+    #[error("Nodes read from each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    #[error("Fixture {fixture} uses profile {profile}, which does not exist")]
+    UnknownProfile { fixture: String, profile: String },
+    #[error(
+        "Channel {channel} of profile {profile} is {bits} bits, but only 8 and 16 are supported"
+    )]
+    ChannelBits {
+        profile: String,
+        channel: String,
+        bits: u8,
+    },
+    #[error("Profile {profile} names channel {channel} more than once")]
+    DuplicateChannel { profile: String, channel: String },
+    #[error("Fixture {fixture} at address {address} doesn't fit in a universe")]
+    FixtureAddress { fixture: String, address: u16 },
+    #[error("Node {node} refers to {attribute}, which is not a fixture attribute")]
+    UnknownAttribute { node: String, attribute: String },
+    #[error("Node {node} sets {attribute} to {value}, which doesn't fit in {bits} bits")]
+    LevelRange {
+        node: String,
+        attribute: String,
+        value: u32,
+        bits: u8,
+    },
+    #[error("Node {node} patches {target} from {from}, which is a different width")]
+    PatchWidth {
+        node: String,
+        target: String,
+        from: String,
+    },
+    #[error("Node {node} patches {target} from channel {channel}, which runs past the universe")]
+    PatchChannel {
+        node: String,
+        target: String,
+        channel: u16,
+    },
 }
 
 pub type PairList<K, V> = Vec<(K, V)>;
@@ -39,28 +87,130 @@ pub struct Config {
         deserialize_with = "deserialize_duration"
     )]
     pub shutdown_grace_period: Duration,
+    #[serde(default)]
+    pub record: Option<RecordConfig>,
+    #[serde(default, deserialize_with = "deserialize_pair_list")]
+    pub profile: PairList<String, ProfileConfig>,
+    #[serde(default, deserialize_with = "deserialize_pair_list")]
+    pub fixture: PairList<String, FixtureConfig>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct InputConfig {
     pub name: String,
     pub universe: u32,
+    #[serde(
+        default = "default_input_timeout",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub timeout: Duration,
+    #[serde(default)]
+    pub on_loss: LossConfig,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LossConfig {
+    #[default]
+    Hold,
+    Fade(#[serde(deserialize_with = "deserialize_duration")] Duration),
+    Universe(u32),
+    Node(String),
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct OutputConfig {
-    pub universe: u32,
+    #[serde(deserialize_with = "deserialize_one_or_many")]
+    pub universe: Vec<u32>,
     pub from: String,
+    #[serde(default, deserialize_with = "deserialize_rate")]
+    pub refresh_rate: Option<Duration>,
+    #[serde(default = "default_output_backends")]
+    pub backend: Vec<BackendConfig>,
+    #[serde(default)]
+    pub on_shutdown: ShutdownConfig,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub shutdown_fade: Option<Duration>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BackendConfig {
+    Ola,
+    Sacn {
+        destination: Option<IpAddr>,
+        priority: Option<u8>,
+        source_name: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RecordConfig {
+    pub directory: PathBuf,
+    pub nodes: Vec<String>,
+}
+
+// the channels of a kind of fixture, in address order
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub channels: Vec<ChannelConfig>,
+}
+
+// a channel is its name, or a table to give a 16 bit width, which takes a
+// coarse channel followed by a fine one
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ChannelConfig {
+    Name(String),
+    Sized {
+        name: String,
+        #[serde(default = "default_channel_bits")]
+        bits: u8,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureConfig {
+    pub profile: String,
+    // the fixture's first channel, counting from 1 like a console
+    pub address: u16,
+}
+
+// where a rewire reads a fixture or attribute from: a channel counting from
+// 1, or another fixture or attribute of the same width
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum PatchSource {
+    Channel(u16),
+    Fixture(String),
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShutdownConfig {
+    #[default]
+    Hold,
+    Blackout,
+    // a static node's channels as written in the config. edits made to the
+    // node over the admin protocol don't change what's sent at shutdown
+    Node(String),
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum NodeConfig {
     Static {
-        #[serde(deserialize_with = "deserialize_buffer")]
+        #[serde(default = "DmxBuffer::new", deserialize_with = "deserialize_buffer")]
         channels: DmxBuffer,
+        // fixture attributes set over the channels, like "wash.red" = 255.
+        // cleared once they're resolved
+        #[serde(default, deserialize_with = "deserialize_pair_list")]
+        levels: PairList<String, u32>,
     },
     Add {
         a: Option<String>,
@@ -72,18 +222,149 @@ pub enum NodeConfig {
     },
     Rewire {
         input: Option<String>,
+        // left out, each channel reads from itself
+        #[serde(default)]
         map: Vec<u16>,
+        // fixtures or attributes and where they read from, set over the map.
+        // cleared once they're resolved
+        #[serde(default, deserialize_with = "deserialize_pair_list")]
+        patch: PairList<String, PatchSource>,
+    },
+    Playback {
+        file: PathBuf,
+        stream: Option<String>,
+        #[serde(default, rename = "loop")]
+        looping: bool,
+        #[serde(default = "default_playback_speed")]
+        speed: f64,
+        #[serde(default, deserialize_with = "deserialize_optional_duration")]
+        start: Option<Duration>,
     },
 }
 
 impl Config {
     pub fn try_from_file(file: &Path) -> Result<Self, Error> {
-        if file.exists() {
-            let text = read_to_string(file).expect("read config file");
-            Ok(toml::from_str(&text)?)
-        } else {
-            Ok(Default::default())
+        let text = read_to_string(file).map_err(|e| Error::ReadError(file.to_path_buf(), e))?;
+        let mut config: Config = toml::from_str(&text)?;
+        fixture::resolve(&mut config)?;
+        config.sort_nodes()?;
+
+        Ok(config)
+    }
+
+    // nodes are declared in any order, but inserted after the nodes they read
+    // from. otherwise file order is kept
+    pub fn sort_nodes(&mut self) -> Result<(), Error> {
+        let index: HashMap<&str, usize> = self
+            .node
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (key.as_str(), i))
+            .collect();
+        let inputs: HashSet<&str> = self.input.iter().map(|(key, _)| key.as_str()).collect();
+
+        for (key, node) in &self.node {
+            if let Some((_, reference)) = node
+                .references()
+                .find(|(_, r)| !index.contains_key(r) && !inputs.contains(r))
+            {
+                return Err(Error::UnknownReference {
+                    node: key.clone(),
+                    reference: reference.to_string(),
+                });
+            }
         }
+
+        let mut sort = Sort {
+            nodes: &self.node,
+            index: &index,
+            visits: vec![Visit::New; self.node.len()],
+            path: Vec::new(),
+            order: Vec::with_capacity(self.node.len()),
+        };
+        for i in 0..self.node.len() {
+            sort.visit(i)?;
+        }
+        let order = sort.order;
+
+        let mut nodes: Vec<_> = take(&mut self.node).into_iter().map(Some).collect();
+        self.node = order
+            .into_iter()
+            .map(|i| nodes[i].take().expect("visit each node once"))
+            .collect();
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    New,
+    Active,
+    Done,
+}
+
+// a depth first walk, where meeting a node that's still on the path means
+// the path has looped back on itself
+struct Sort<'a> {
+    nodes: &'a PairList<String, NodeConfig>,
+    index: &'a HashMap<&'a str, usize>,
+    visits: Vec<Visit>,
+    path: Vec<usize>,
+    order: Vec<usize>,
+}
+
+impl Sort<'_> {
+    fn visit(&mut self, i: usize) -> Result<(), Error> {
+        match self.visits[i] {
+            Visit::Done => return Ok(()),
+            Visit::Active => {
+                let start = self
+                    .path
+                    .iter()
+                    .position(|p| *p == i)
+                    .expect("find active node on path");
+                let cycle = self.path[start..]
+                    .iter()
+                    .chain([&i])
+                    .map(|p| self.nodes[*p].0.clone())
+                    .collect();
+
+                return Err(Error::Cycle(cycle));
+            }
+            Visit::New => {}
+        }
+
+        self.visits[i] = Visit::Active;
+        self.path.push(i);
+        for (_, reference) in self.nodes[i].1.references() {
+            // anything else is an input, which doesn't read from nodes
+            if let Some(j) = self.index.get(reference) {
+                self.visit(*j)?;
+            }
+        }
+        self.path.pop();
+        self.visits[i] = Visit::Done;
+        self.order.push(i);
+
+        Ok(())
+    }
+}
+
+impl NodeConfig {
+    // the nodes this one reads from, by the field naming them
+    pub fn references(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        let references = match self {
+            NodeConfig::Add { a, b } | NodeConfig::Multiply { a, b } => {
+                [("a", a.as_deref()), ("b", b.as_deref())]
+            }
+            NodeConfig::Rewire { input, .. } => [("input", input.as_deref()), ("input", None)],
+            NodeConfig::Static { .. } | NodeConfig::Playback { .. } => [("", None), ("", None)],
+        };
+
+        references
+            .into_iter()
+            .filter_map(|(field, reference)| Some((field, reference?)))
     }
 }
 
@@ -95,6 +376,9 @@ impl Default for Config {
             output: Default::default(),
             node: Default::default(),
             shutdown_grace_period: default_shutdown_grace_period(),
+            record: None,
+            profile: Default::default(),
+            fixture: Default::default(),
         }
     }
 }
@@ -103,6 +387,22 @@ fn default_shutdown_grace_period() -> Duration {
     DEFAULT_SHUTDOWN_GRACE_PERIOD
 }
 
+fn default_input_timeout() -> Duration {
+    DEFAULT_INPUT_TIMEOUT
+}
+
+fn default_playback_speed() -> f64 {
+    1.0
+}
+
+fn default_channel_bits() -> u8 {
+    8
+}
+
+fn default_output_backends() -> Vec<BackendConfig> {
+    vec![BackendConfig::Ola]
+}
+
 pub fn deserialize_pair_list<'de, D, K, V>(deserializer: D) -> Result<PairList<K, V>, D::Error>
 where
     D: Deserializer<'de>,
@@ -140,6 +440,24 @@ where
     deserializer.deserialize_map(PairVisitor { m: PhantomData })
 }
 
+pub fn deserialize_one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => Ok(vec![value]),
+        OneOrMany::Many(values) => Ok(values),
+    }
+}
+
 pub fn deserialize_buffer<'de, D>(deserializer: D) -> Result<DmxBuffer, D::Error>
 where
     D: Deserializer<'de>,
@@ -196,3 +514,31 @@ where
         Some(s) => Err(D::Error::invalid_value(Unexpected::Str(s), &"s, m, or h")),
     }
 }
+
+pub fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+pub fn deserialize_rate<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hz = f64::deserialize(deserializer)?;
+    // rates so low or high that the period doesn't fit a duration, or rounds
+    // to nothing, are refused along with the rest
+    let period = Some(hz)
+        .filter(|hz| hz.is_finite() && *hz > 0.0)
+        .and_then(|hz| Duration::try_from_secs_f64(1.0 / hz).ok())
+        .filter(|period| !period.is_zero());
+
+    match period {
+        Some(period) => Ok(Some(period)),
+        None => Err(D::Error::invalid_value(
+            Unexpected::Float(hz),
+            &"a positive refresh rate in Hz",
+        )),
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::mem::take;
+
+use crate::config::{ChannelConfig, Config, Error, NodeConfig, PatchSource};
+
+// channels of a universe taken by a fixture or one of its attributes
+#[derive(Clone, Copy, Debug)]
+struct Span {
+    start: usize,
+    width: usize,
+}
+
+// where every fixture and attribute sits, by fixture and then attribute name
+struct Library {
+    fixtures: HashMap<String, (Span, HashMap<String, Span>)>,
+}
+
+// turn the fixture levels and patches in static and rewire nodes into plain
+// channels and maps, so nothing past the config has to know about fixtures
+pub fn resolve(config: &mut Config) -> Result<(), Error> {
+    let library = Library::new(config)?;
+
+    for (key, node) in &mut config.node {
+        match node {
+            NodeConfig::Static { channels, levels } if !levels.is_empty() => {
+                let mut buffer = Vec::from(channels.clone());
+                for (attribute, value) in take(levels) {
+                    library.set(key, &mut buffer, &attribute, value)?;
+                }
+                *channels = buffer.try_into().expect("keep universe size");
+            }
+            NodeConfig::Rewire { map, patch, .. } => {
+                if map.is_empty() {
+                    *map = (0..512).collect();
+                }
+                // a map of the wrong size is rejected with the node
+                if map.len() != 512 {
+                    continue;
+                }
+                for (target, from) in take(patch) {
+                    library.patch(key, map, &target, &from)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+impl Library {
+    fn new(config: &Config) -> Result<Self, Error> {
+        let mut profiles = HashMap::new();
+        for (key, profile) in &config.profile {
+            let mut channels: HashMap<String, Span> = HashMap::new();
+            let mut offset = 0;
+            for channel in &profile.channels {
+                let (name, bits) = match channel {
+                    ChannelConfig::Name(name) => (name, 8),
+                    ChannelConfig::Sized { name, bits } => (name, *bits),
+                };
+                if bits != 8 && bits != 16 {
+                    return Err(Error::ChannelBits {
+                        profile: key.clone(),
+                        channel: name.clone(),
+                        bits,
+                    });
+                }
+
+                let span = Span {
+                    start: offset,
+                    width: bits as usize / 8,
+                };
+                if channels.insert(name.clone(), span).is_some() {
+                    return Err(Error::DuplicateChannel {
+                        profile: key.clone(),
+                        channel: name.clone(),
+                    });
+                }
+                offset += span.width;
+            }
+
+            profiles.insert(key.as_str(), (offset, channels));
+        }
+
+        let mut fixtures = HashMap::new();
+        for (key, fixture) in &config.fixture {
+            let (width, channels) =
+                profiles
+                    .get(fixture.profile.as_str())
+                    .ok_or_else(|| Error::UnknownProfile {
+                        fixture: key.clone(),
+                        profile: fixture.profile.clone(),
+                    })?;
+            let start = (fixture.address as usize).wrapping_sub(1);
+            if start >= 512 || start + width > 512 {
+                return Err(Error::FixtureAddress {
+                    fixture: key.clone(),
+                    address: fixture.address,
+                });
+            }
+
+            let attributes = channels
+                .iter()
+                .map(|(name, span)| {
+                    let span = Span {
+                        start: start + span.start,
+                        width: span.width,
+                    };
+                    (name.clone(), span)
+                })
+                .collect();
+            fixtures.insert(
+                key.clone(),
+                (
+                    Span {
+                        start,
+                        width: *width,
+                    },
+                    attributes,
+                ),
+            );
+        }
+
+        Ok(Self { fixtures })
+    }
+
+    // a `fixture.attribute`, or with `whole`, also a bare fixture
+    fn find(&self, node: &str, name: &str, whole: bool) -> Result<Span, Error> {
+        let span = match name.split_once('.') {
+            Some((fixture, attribute)) => self
+                .fixtures
+                .get(fixture)
+                .and_then(|(_, attributes)| attributes.get(attribute))
+                .copied(),
+            None if whole => self.fixtures.get(name).map(|(span, _)| *span),
+            None => None,
+        };
+
+        span.ok_or_else(|| Error::UnknownAttribute {
+            node: node.to_string(),
+            attribute: name.to_string(),
+        })
+    }
+
+    // 16 bit attributes take the high byte on the coarse channel
+    fn set(
+        &self,
+        node: &str,
+        channels: &mut [u8],
+        attribute: &str,
+        value: u32,
+    ) -> Result<(), Error> {
+        let span = self.find(node, attribute, false)?;
+        let bits = span.width as u8 * 8;
+        if value >= 1 << bits {
+            return Err(Error::LevelRange {
+                node: node.to_string(),
+                attribute: attribute.to_string(),
+                value,
+                bits,
+            });
+        }
+
+        let bytes = value.to_be_bytes();
+        channels[span.start..span.start + span.width].copy_from_slice(&bytes[4 - span.width..]);
+
+        Ok(())
+    }
+
+    fn patch(
+        &self,
+        node: &str,
+        map: &mut [u16],
+        target: &str,
+        from: &PatchSource,
+    ) -> Result<(), Error> {
+        let span = self.find(node, target, true)?;
+        let start = match from {
+            PatchSource::Channel(channel) => {
+                let start = (*channel as usize).wrapping_sub(1);
+                if start >= 512 || start + span.width > 512 {
+                    return Err(Error::PatchChannel {
+                        node: node.to_string(),
+                        target: target.to_string(),
+                        channel: *channel,
+                    });
+                }
+                start
+            }
+            PatchSource::Fixture(name) => {
+                let source = self.find(node, name, true)?;
+                if source.width != span.width {
+                    return Err(Error::PatchWidth {
+                        node: node.to_string(),
+                        target: target.to_string(),
+                        from: name.clone(),
+                    });
+                }
+                source.start
+            }
+        };
+
+        for i in 0..span.width {
+            map[span.start + i] = (start + i) as u16;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FixtureConfig, ProfileConfig};
+
+    // three channels: an 8 bit dimmer, then a 16 bit pan
+    fn library(fixtures: &[(&str, u16)]) -> Result<Library, Error> {
+        let channels = vec![
+            ChannelConfig::Name("dim".to_string()),
+            ChannelConfig::Sized {
+                name: "pan".to_string(),
+                bits: 16,
+            },
+        ];
+        let config = Config {
+            profile: vec![("spot".to_string(), ProfileConfig { channels })],
+            fixture: fixtures
+                .iter()
+                .map(|(name, address)| {
+                    let fixture = FixtureConfig {
+                        profile: "spot".to_string(),
+                        address: *address,
+                    };
+                    (name.to_string(), fixture)
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        Library::new(&config)
+    }
+
+    fn fixture_address(result: Result<Library, Error>, expected: u16) {
+        match result {
+            Err(Error::FixtureAddress { address, .. }) => assert_eq!(address, expected),
+            Err(e) => panic!("expected a fixture address error, got {}", e),
+            Ok(_) => panic!("expected a fixture address error"),
+        }
+    }
+
+    #[test]
+    fn addresses_fit_the_universe() {
+        let first = library(&[("a", 1)]).unwrap();
+        assert_eq!(first.find("n", "a.dim", false).unwrap().start, 0);
+        assert_eq!(first.find("n", "a", true).unwrap().width, 3);
+
+        // the fine channel of the pan lands on 512
+        let last = library(&[("a", 510)]).unwrap();
+        let pan = last.find("n", "a.pan", false).unwrap();
+        assert_eq!((pan.start, pan.width), (510, 2));
+
+        fixture_address(library(&[("a", 0)]), 0);
+        fixture_address(library(&[("a", 511)]), 511);
+        fixture_address(library(&[("a", 512)]), 512);
+        fixture_address(library(&[("a", 513)]), 513);
+    }
+
+    #[test]
+    fn profiles_need_known_widths() {
+        let config = Config {
+            profile: vec![(
+                "odd".to_string(),
+                ProfileConfig {
+                    channels: vec![ChannelConfig::Sized {
+                        name: "zoom".to_string(),
+                        bits: 12,
+                    }],
+                },
+            )],
+            ..Default::default()
+        };
+        assert!(matches!(
+            Library::new(&config),
+            Err(Error::ChannelBits { bits: 12, .. })
+        ));
+    }
+
+    #[test]
+    fn levels_split_into_coarse_and_fine() {
+        let library = library(&[("a", 510)]).unwrap();
+        let mut channels = vec![0; 512];
+
+        library.set("n", &mut channels, "a.dim", 255).unwrap();
+        library.set("n", &mut channels, "a.pan", 0x1234).unwrap();
+        assert_eq!(channels[509..], [255, 0x12, 0x34]);
+        library.set("n", &mut channels, "a.pan", 0xffff).unwrap();
+        assert_eq!(channels[510..], [0xff, 0xff]);
+
+        let range = |attribute, value| match library.set("n", &mut vec![0; 512], attribute, value) {
+            Err(Error::LevelRange { bits, .. }) => bits,
+            result => panic!("expected a level range error, got {:?}", result.err()),
+        };
+        assert_eq!(range("a.dim", 256), 8);
+        assert_eq!(range("a.pan", 0x10000), 16);
+
+        // a whole fixture has no single level
+        assert!(matches!(
+            library.set("n", &mut channels, "a", 0),
+            Err(Error::UnknownAttribute { .. })
+        ));
+    }
+
+    #[test]
+    fn patches_match_widths() {
+        let library = library(&[("a", 1), ("b", 510)]).unwrap();
+        let mut map: Vec<u16> = (0..512).collect();
+
+        let from = PatchSource::Fixture("a.pan".to_string());
+        library.patch("n", &mut map, "b.pan", &from).unwrap();
+        assert_eq!(map[510..], [1, 2]);
+        let from = PatchSource::Fixture("a".to_string());
+        library.patch("n", &mut map, "b", &from).unwrap();
+        assert_eq!(map[509..], [0, 1, 2]);
+
+        let from = PatchSource::Fixture("a.dim".to_string());
+        assert!(matches!(
+            library.patch("n", &mut map, "b.pan", &from),
+            Err(Error::PatchWidth { .. })
+        ));
+        let from = PatchSource::Fixture("a.pan".to_string());
+        assert!(matches!(
+            library.patch("n", &mut map, "b", &from),
+            Err(Error::PatchWidth { .. })
+        ));
+    }
+
+    #[test]
+    fn patch_channels_count_from_one() {
+        let library = library(&[("a", 1)]).unwrap();
+        let mut map: Vec<u16> = (0..512).collect();
+
+        library
+            .patch("n", &mut map, "a.dim", &PatchSource::Channel(512))
+            .unwrap();
+        assert_eq!(map[0], 511);
+        library
+            .patch("n", &mut map, "a.pan", &PatchSource::Channel(511))
+            .unwrap();
+        assert_eq!(map[1..3], [510, 511]);
+
+        let channel = |target, channel| match library.patch(
+            "n",
+            &mut vec![0; 512],
+            target,
+            &PatchSource::Channel(channel),
+        ) {
+            Err(Error::PatchChannel { channel, .. }) => channel,
+            result => panic!("expected a patch channel error, got {:?}", result.err()),
+        };
+        assert_eq!(channel("a.dim", 0), 0);
+        assert_eq!(channel("a.dim", 513), 513);
+        // the fine channel would be past the end
+        assert_eq!(channel("a.pan", 512), 512);
+    }
+}
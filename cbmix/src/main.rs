@@ -1,31 +1,118 @@
+mod check;
 pub mod config;
+mod fixture;
+mod patch;
 
 use std::env::var;
+use std::io::{stdout, IsTerminal};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::process::exit;
 
-use config::{Config, InputConfig, NodeConfig, OutputConfig};
+use config::Config;
+use patch::{scene_id, Patch};
 
-use anyhow::{anyhow, Error};
 use cbmix_admin::Admin;
 use cbmix_common::shutdown;
 use cbmix_dmx::Dmx;
-use cbmix_graph::{Graph, GraphHandle, Node, NAMESPACE_SCENE};
+use cbmix_graph::Graph;
+use cbmix_record::{players, Recorder};
+use clap::{Parser, Subcommand, ValueEnum};
 use directories::ProjectDirs;
 use tokio::{
     runtime::Runtime,
-    signal::unix::{signal, SignalKind},
+    signal::unix::{signal, Signal, SignalKind},
     time::timeout,
 };
 use tracing::{debug, error, info, info_span, instrument::Instrument, warn};
-use uuid::Uuid;
+
+/// Mix DMX universes through a graph of nodes
+#[derive(Parser)]
+struct Args {
+    /// Config file [default: config.toml in the user config directory]
+    #[arg(long, env = "CBMIX_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// Directory for snapshots and other saved state [default: the user state
+    /// directory]
+    #[arg(long, env = "CBMIX_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+    /// Admin address to listen on, in place of the config's listen_addr
+    #[arg(long, env = "CBMIX_LISTEN_ADDR")]
+    listen_addr: Option<SocketAddr>,
+    /// Log filter, like info or cbmix_dmx=debug [default: RUST_LOG, or info]
+    #[arg(long, env = "CBMIX_LOG")]
+    log: Option<String>,
+    /// Log line format
+    #[arg(long, env = "CBMIX_LOG_FORMAT", value_enum, default_value_t)]
+    log_format: LogFormat,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check a config file for mistakes without connecting to anything
+    Check {
+        /// Config file to check [default: the --config file]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Pretty,
+}
+
+// where an instance keeps its files, so several can run side by side
+struct Instance {
+    config: PathBuf,
+    // whether `config` is the default location rather than one given
+    default_config: bool,
+    state_dir: PathBuf,
+    listen_addr: Option<SocketAddr>,
+}
 
 fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(var("RUST_LOG").unwrap_or_else(|_| "info".to_string()))
-        .init();
+    let args = Args::parse();
+
+    let filter = args
+        .log
+        .or_else(|| var("RUST_LOG").ok())
+        .unwrap_or_else(|| "info".to_string());
+    // colors only get in the way of journald and log files
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(stdout().is_terminal());
+    match args.log_format {
+        LogFormat::Full => logs.init(),
+        LogFormat::Compact => logs.compact().init(),
+        LogFormat::Pretty => logs.pretty().init(),
+    }
+
+    let dirs = || ProjectDirs::from("", "", "cbmix").expect("determine program directories");
+    let instance = Instance {
+        default_config: args.config.is_none(),
+        config: args
+            .config
+            .unwrap_or_else(|| dirs().config_dir().join("config.toml")),
+        state_dir: args.state_dir.unwrap_or_else(|| {
+            let dirs = dirs();
+            dirs.state_dir()
+                .unwrap_or(dirs.data_local_dir())
+                .to_path_buf()
+        }),
+        listen_addr: args.listen_addr,
+    };
 
-    let dirs = ProjectDirs::from("", "", "cbmix").expect("determine program directories");
-    let config = match Config::try_from_file(&dirs.config_dir().join("config.toml")) {
+    if let Some(Command::Check { path }) = args.command {
+        let path = path.unwrap_or(instance.config);
+        exit(if check::check(&path) { 0 } else { 1 });
+    }
+
+    let config = match load_config(&instance) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("{}", e);
@@ -38,7 +125,7 @@ fn main() {
 
         let graph = Graph::new(shutdown.subscribe());
 
-        let mut dmx = match Dmx::new(graph.handle(), shutdown.subscribe()).await {
+        let dmx = match Dmx::new(graph.handle(), shutdown.subscribe()).await {
             Ok(dmx) => dmx,
             Err(e) => {
                 eprintln!("Error setting up DMX connections\n{}", e);
@@ -46,37 +133,92 @@ fn main() {
             }
         };
 
-        let admin = Admin::new(config.admin.clone(), graph.handle(), shutdown.subscribe());
+        let (player_handles, players) = players::channel();
+        let admin = Admin::new(
+            config.admin.clone(),
+            graph.handle(),
+            dmx.input_status(),
+            players,
+            shutdown.subscribe(),
+        );
+
+        let recorder = config.record.as_ref().map(|record| {
+            let nodes = record
+                .nodes
+                .iter()
+                .map(|n| (n.clone(), scene_id(n)))
+                .collect();
 
-        let init_handle = graph.handle();
+            Recorder::new(
+                record.directory.clone(),
+                nodes,
+                graph.handle(),
+                shutdown.subscribe(),
+            )
+        });
+
+        let mut patch = Patch::new(
+            graph.handle(),
+            dmx.handle(),
+            player_handles,
+            shutdown.subscribe(),
+        );
 
         tokio::spawn(graph.serve().instrument(info_span!("graph")));
+        tokio::spawn(dmx.serve().instrument(info_span!("dmx")));
 
-        match register_nodes(&config, init_handle, &mut dmx).await {
+        let mut admin = match patch.apply(&config).await {
             Ok(()) => {
-                tokio::spawn(dmx.serve().instrument(info_span!("dmx")));
-                tokio::spawn(admin.serve().instrument(info_span!("admin")));
+                if let Some(recorder) = recorder {
+                    tokio::spawn(recorder.serve().instrument(info_span!("recorder")));
+                }
+                Some(tokio::spawn(admin.serve().instrument(info_span!("admin"))))
             }
             Err(e) => {
                 error!("failed to register nodes from config file: {}", e);
                 shutdown.subscribe().force_shutdown().await;
-                drop(dmx);
                 drop(admin);
+                drop(recorder);
+                None
             }
-        }
+        };
+        let mut failed = false;
 
-        tokio::select! {
-            _ = unix_signal(SignalKind::interrupt()) => {
-                info!("received SIGINT, shutting down");
-            },
-            _ = unix_signal(SignalKind::terminate()) => {
-                info!("received SIGTERM, shutting down");
-            },
-            _ = shutdown.recv() => {
-                error!("shutting down due to unexpected error");
-            },
+        let mut hangup = listen(SignalKind::hangup());
+        let mut interrupt = listen(SignalKind::interrupt());
+        let mut terminate = listen(SignalKind::terminate());
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    info!("received SIGHUP, reloading config");
+                    reload(&instance, &config, &mut patch).await;
+                },
+                _ = interrupt.recv() => {
+                    info!("received SIGINT, shutting down");
+                    break;
+                },
+                _ = terminate.recv() => {
+                    info!("received SIGTERM, shutting down");
+                    break;
+                },
+                _ = shutdown.recv() => {
+                    error!("shutting down due to unexpected error");
+                    break;
+                },
+                // the admin server only returns early when it can't start
+                result = async { admin.as_mut().unwrap().await }, if admin.is_some() => {
+                    admin = None;
+                    if let Ok(Err(e)) = result {
+                        error!("failed to start admin server: {}", e);
+                        failed = true;
+                        break;
+                    }
+                },
+            }
         }
 
+        // the patch holds a shutdown receiver for the players it starts
+        drop(patch);
         match timeout(config.shutdown_grace_period, shutdown.shutdown()).await {
             Ok(()) => debug!("shutdown completed"),
             Err(_) => warn!(
@@ -84,75 +226,70 @@ fn main() {
                 config.shutdown_grace_period
             ),
         }
+        if failed {
+            exit(1);
+        }
     })
 }
 
-fn build_runtime() -> Runtime {
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .expect("build a multi-threaded tokio runtime")
-}
+// a first run can go without a config at the default location, but a
+// config that was asked for has to be there
+fn load_config(instance: &Instance) -> Result<Config, config::Error> {
+    let mut config = if instance.default_config && !instance.config.exists() {
+        Config::default()
+    } else {
+        Config::try_from_file(&instance.config)?
+    };
+    if let Some(listen_addr) = instance.listen_addr {
+        config.admin.listen_addr = listen_addr;
+    }
+    config
+        .admin
+        .snapshot_dir
+        .get_or_insert_with(|| instance.state_dir.join("snapshots"));
 
-async fn unix_signal(kind: SignalKind) {
-    signal(kind)
-        .expect("register a unix signal handler")
-        .recv()
-        .await;
+    Ok(config)
 }
 
-async fn register_nodes(config: &Config, graph: GraphHandle, dmx: &mut Dmx) -> Result<(), Error> {
-    for (id, InputConfig { universe, .. }) in &config.input {
-        dmx.add_input(*universe, Uuid::new_v5(&NAMESPACE_SCENE, id.as_bytes()))
-            .await?;
+// only the patch is reloaded, everything else is set up once at startup
+async fn reload(instance: &Instance, running: &Config, patch: &mut Patch) {
+    // a config that was moved away shouldn't tear the whole rig down
+    if !instance.config.exists() {
+        error!(
+            "keeping the current config, {} is missing",
+            instance.config.display()
+        );
+        return;
     }
 
-    for (id, node) in &config.node {
-        let id = Uuid::new_v5(&NAMESPACE_SCENE, id.as_bytes());
-        let node = match node {
-            NodeConfig::Static { channels } => Node::Input {
-                channels: channels.clone(),
-            },
-            NodeConfig::Add { a, b } => {
-                let a = a
-                    .as_ref()
-                    .map(|s| Uuid::new_v5(&NAMESPACE_SCENE, s.as_bytes()));
-                let b = b
-                    .as_ref()
-                    .map(|s| Uuid::new_v5(&NAMESPACE_SCENE, s.as_bytes()));
-                Node::Add { a, b }
-            }
-            NodeConfig::Multiply { a, b } => {
-                let a = a
-                    .as_ref()
-                    .map(|s| Uuid::new_v5(&NAMESPACE_SCENE, s.as_bytes()));
-                let b = b
-                    .as_ref()
-                    .map(|s| Uuid::new_v5(&NAMESPACE_SCENE, s.as_bytes()));
-                Node::Multiply { a, b }
-            }
-            NodeConfig::Rewire { input, map } => {
-                let input = input
-                    .as_ref()
-                    .map(|s| Uuid::new_v5(&NAMESPACE_SCENE, s.as_bytes()));
-                Node::Rewire {
-                    input,
-                    map: Box::new(
-                        (&map[..])
-                            .try_into()
-                            .map_err(|_| anyhow!("rewire map was not 512 long"))?,
-                    ),
-                }
-            }
-        };
+    let config = match load_config(instance) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("keeping the current config: {}", e);
+            return;
+        }
+    };
 
-        graph.insert(id, node).await?;
+    if config.admin != running.admin
+        || config.record != running.record
+        || config.shutdown_grace_period != running.shutdown_grace_period
+    {
+        warn!("changes to admin, record, and shutdown_grace_period need a restart");
     }
 
-    for (_, OutputConfig { universe, from }) in config.output.iter() {
-        dmx.add_output(*universe, Uuid::new_v5(&NAMESPACE_SCENE, from.as_bytes()))
-            .await?;
+    match patch.apply(&config).await {
+        Ok(()) => info!("reloaded config"),
+        Err(e) => error!("failed to apply reloaded config: {}", e),
     }
+}
+
+fn build_runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("build a multi-threaded tokio runtime")
+}
 
-    Ok(())
+fn listen(kind: SignalKind) -> Signal {
+    signal(kind).expect("register a unix signal handler")
 }
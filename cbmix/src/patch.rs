@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{
+    BackendConfig, Config, InputConfig, LossConfig, NodeConfig, OutputConfig, ShutdownConfig,
+};
+
+use anyhow::{anyhow, Error};
+use cbmix_common::shutdown;
+use cbmix_dmx::{Backend, DmxHandle, LossPolicy, OutputOptions, SacnOptions, ShutdownLook};
+use cbmix_graph::{Error as GraphError, GraphHandle, Node, NAMESPACE_SCENE};
+use cbmix_record::{players, PlaybackOptions, Player};
+use ola::DmxBuffer;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info_span, instrument::Instrument};
+use uuid::Uuid;
+
+struct AppliedOutput {
+    config: OutputConfig,
+    look: ShutdownLook,
+    id: Uuid,
+}
+
+// the inputs, nodes, and outputs applied from the config file, so a reloaded
+// config only touches what changed and unaffected outputs keep running
+pub struct Patch {
+    graph: GraphHandle,
+    dmx: DmxHandle,
+    shutdown: shutdown::Receiver,
+    inputs: HashMap<String, InputConfig>,
+    nodes: HashMap<String, NodeConfig>,
+    outputs: HashMap<String, AppliedOutput>,
+    players: HashMap<String, JoinHandle<()>>,
+    // the running players' handles, for the admin server to control them with
+    handles: players::Sender,
+}
+
+impl Patch {
+    pub fn new(
+        graph: GraphHandle,
+        dmx: DmxHandle,
+        handles: players::Sender,
+        shutdown: shutdown::Receiver,
+    ) -> Self {
+        Self {
+            graph,
+            dmx,
+            shutdown,
+            inputs: HashMap::new(),
+            nodes: HashMap::new(),
+            outputs: HashMap::new(),
+            players: HashMap::new(),
+            handles,
+        }
+    }
+
+    // a config that can't be resolved is rejected before anything changes.
+    // past that, each change is made on its own and a failed one is logged
+    // and skipped. only what was applied is tracked, so the next reload
+    // retries the rest, and the error at the end says how many failed
+    pub async fn apply(&mut self, config: &Config) -> Result<(), Error> {
+        let mut looks = HashMap::new();
+        for (key, output) in &config.output {
+            looks.insert(key.as_str(), shutdown_look(config, output)?);
+            // a longer fade would be cut off before sACN receivers are told
+            // the universe is going away
+            if let Some(fade) = output.shutdown_fade {
+                if fade >= config.shutdown_grace_period {
+                    return Err(anyhow!(
+                        "output {} fades for {:?} on shutdown, which doesn't fit in the {:?} grace period",
+                        key,
+                        fade,
+                        config.shutdown_grace_period
+                    ));
+                }
+            }
+        }
+
+        let mut changed = Vec::new();
+        for (key, node) in &config.node {
+            if self.nodes.get(key) != Some(node) {
+                let id = scene_id(key);
+                let body = to_node(node)?;
+                let player = self.open_player(key, id, node).await?;
+                changed.push((key, node, id, body, player));
+            }
+        }
+
+        let wanted: HashSet<&str> = config
+            .input
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .chain(config.node.iter().map(|(key, _)| key.as_str()))
+            .collect();
+        let stale: HashSet<String> = self
+            .inputs
+            .keys()
+            .chain(self.nodes.keys())
+            .filter(|key| !wanted.contains(key.as_str()))
+            .cloned()
+            .collect();
+
+        // inputs that changed are removed and added again
+        let inputs: HashMap<&str, &InputConfig> =
+            config.input.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let removed: Vec<String> = self
+            .inputs
+            .iter()
+            .filter(|(key, input)| inputs.get(key.as_str()).copied() != Some(*input))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut failures = 0;
+        for key in removed {
+            if let Err(e) = self.dmx.remove_input(self.inputs[&key].universe).await {
+                error!("failed to remove input {}: {}", key, e);
+                failures += 1;
+                continue;
+            }
+            self.inputs.remove(&key);
+            debug!("removed input {}", key);
+        }
+        for (key, input) in &config.input {
+            if self.inputs.contains_key(key) {
+                continue;
+            }
+
+            let added = self
+                .dmx
+                .add_input(
+                    input.universe,
+                    scene_id(key),
+                    loss_policy(&input.on_loss),
+                    input.timeout,
+                )
+                .await;
+            if let Err(e) = added {
+                error!("failed to add input {}: {}", key, e);
+                failures += 1;
+                continue;
+            }
+            self.inputs.insert(key.clone(), input.clone());
+            debug!("added input {} on universe {}", key, input.universe);
+        }
+
+        for (key, ..) in &changed {
+            self.stop_player(key);
+        }
+        if !changed.is_empty() {
+            let nodes = changed
+                .iter()
+                .map(|(_, _, id, body, _)| (*id, body.clone()))
+                .collect();
+            // their players are already stopped, so none of them are tracked
+            // if any failed, and the next reload starts them all again
+            if let Err(e) = self.graph.insert_batch(nodes).await {
+                error!("failed to update nodes: {}", e);
+                failures += 1;
+                for (key, ..) in changed.drain(..) {
+                    self.nodes.remove(key);
+                }
+            }
+        }
+        for (key, node, id, _, player) in changed {
+            if let Some(player) = player {
+                self.handles.send_modify(|handles| {
+                    handles.insert(id, player.handle());
+                });
+                let player = tokio::spawn(player.serve().instrument(info_span!("playback")));
+                self.players.insert(key.clone(), player);
+            }
+            self.nodes.insert(key.clone(), node.clone());
+            debug!("updated node {}", key);
+        }
+
+        // replacements start sending before the outputs they replace are
+        // removed, so shared sACN universes aren't terminated in between
+        for (key, output) in &config.output {
+            let look = looks
+                .remove(key.as_str())
+                .expect("resolve every shutdown look");
+            if let Some(applied) = self.outputs.get(key) {
+                if applied.config == *output && applied.look == look {
+                    continue;
+                }
+            }
+
+            let added = self
+                .dmx
+                .add_output(scene_id(&output.from), output_options(output, look.clone()))
+                .await;
+            let id = match added {
+                Ok(id) => id,
+                Err(e) => {
+                    error!("failed to add output {}: {}", key, e);
+                    failures += 1;
+                    continue;
+                }
+            };
+            let applied = AppliedOutput {
+                config: output.clone(),
+                look,
+                id,
+            };
+            // the replacement is tracked either way, as it's the one sending
+            if let Some(old) = self.outputs.insert(key.clone(), applied) {
+                if let Err(e) = self.dmx.remove_output(old.id).await {
+                    error!("failed to remove replaced output {}: {}", key, e);
+                    failures += 1;
+                }
+            }
+            debug!("updated output {}", key);
+        }
+        let removed: Vec<String> = self
+            .outputs
+            .keys()
+            .filter(|key| !config.output.iter().any(|(k, _)| k == *key))
+            .cloned()
+            .collect();
+        for key in removed {
+            if let Err(e) = self.dmx.remove_output(self.outputs[&key].id).await {
+                error!("failed to remove output {}: {}", key, e);
+                failures += 1;
+                continue;
+            }
+            self.outputs.remove(&key);
+            debug!("removed output {}", key);
+        }
+
+        for key in stale {
+            self.stop_player(&key);
+            match self.graph.remove(scene_id(&key)).await {
+                // already removed over the admin protocol
+                Ok(()) | Err(GraphError::MissingNode) => {}
+                Err(e) => {
+                    error!("failed to remove node {}: {}", key, e);
+                    failures += 1;
+                    continue;
+                }
+            }
+            self.nodes.remove(&key);
+            debug!("removed node {}", key);
+        }
+
+        match failures {
+            0 => Ok(()),
+            _ => Err(anyhow!("{} changes failed to apply", failures)),
+        }
+    }
+
+    fn stop_player(&mut self, key: &str) {
+        if let Some(player) = self.players.remove(key) {
+            player.abort();
+            self.handles.send_modify(|handles| {
+                handles.remove(&scene_id(key));
+            });
+        }
+    }
+
+    async fn open_player(
+        &self,
+        key: &str,
+        id: Uuid,
+        node: &NodeConfig,
+    ) -> Result<Option<Player>, Error> {
+        let NodeConfig::Playback {
+            file,
+            stream,
+            looping,
+            speed,
+            start,
+        } = node
+        else {
+            return Ok(None);
+        };
+
+        let options = PlaybackOptions {
+            looping: *looping,
+            speed: *speed,
+            start: start.unwrap_or_default(),
+        };
+        let player = Player::open(
+            file,
+            stream.as_deref(),
+            id,
+            options,
+            self.graph.clone(),
+            self.shutdown.clone(),
+        )
+        .await
+        .map_err(|e| anyhow!("failed to open recording for {}: {}", key, e))?;
+
+        Ok(Some(player))
+    }
+}
+
+pub fn scene_id(key: &str) -> Uuid {
+    Uuid::new_v5(&NAMESPACE_SCENE, key.as_bytes())
+}
+
+fn loss_policy(on_loss: &LossConfig) -> LossPolicy {
+    match on_loss {
+        LossConfig::Hold => LossPolicy::Hold,
+        LossConfig::Fade(duration) => LossPolicy::Fade(*duration),
+        LossConfig::Universe(universe) => LossPolicy::Universe(*universe),
+        LossConfig::Node(node) => LossPolicy::Node(scene_id(node)),
+    }
+}
+
+// playback nodes start out dark until their player sends a frame
+fn to_node(node: &NodeConfig) -> Result<Node, Error> {
+    let reference = |key: &Option<String>| key.as_deref().map(scene_id);
+
+    Ok(match node {
+        NodeConfig::Static { channels, .. } => Node::Input {
+            channels: channels.clone(),
+        },
+        NodeConfig::Add { a, b } => Node::Add {
+            a: reference(a),
+            b: reference(b),
+        },
+        NodeConfig::Multiply { a, b } => Node::Multiply {
+            a: reference(a),
+            b: reference(b),
+        },
+        NodeConfig::Rewire { input, map, .. } => {
+            if map.iter().any(|channel| *channel >= 512) {
+                return Err(anyhow!("rewire map has channels past 511"));
+            }
+
+            Node::Rewire {
+                input: reference(input),
+                map: Box::new(
+                    (&map[..])
+                        .try_into()
+                        .map_err(|_| anyhow!("rewire map was not 512 long"))?,
+                ),
+            }
+        }
+        NodeConfig::Playback { .. } => Node::Input {
+            channels: DmxBuffer::new(),
+        },
+    })
+}
+
+// a shutdown node's channels are copied now, so they're what the config
+// says even if the node is edited later
+fn shutdown_look(config: &Config, output: &OutputConfig) -> Result<ShutdownLook, Error> {
+    Ok(match &output.on_shutdown {
+        ShutdownConfig::Hold => ShutdownLook::Hold,
+        ShutdownConfig::Blackout => ShutdownLook::Blackout,
+        ShutdownConfig::Node(node) => match config.node.iter().find(|(id, _)| id == node) {
+            Some((_, NodeConfig::Static { channels, .. })) => {
+                ShutdownLook::Static(Box::new(channels.clone()))
+            }
+            Some(_) => return Err(anyhow!("shutdown node {} is not a static node", node)),
+            None => return Err(anyhow!("shutdown node {} does not exist", node)),
+        },
+    })
+}
+
+fn output_options(output: &OutputConfig, shutdown_look: ShutdownLook) -> OutputOptions {
+    let backends = output
+        .backend
+        .iter()
+        .map(|backend| match backend {
+            BackendConfig::Ola => Backend::Ola,
+            BackendConfig::Sacn {
+                destination,
+                priority,
+                source_name,
+            } => {
+                let defaults = SacnOptions::default();
+                Backend::Sacn(SacnOptions {
+                    destination: *destination,
+                    priority: priority.unwrap_or(defaults.priority),
+                    source_name: source_name.clone().unwrap_or(defaults.source_name),
+                })
+            }
+        })
+        .collect();
+
+    OutputOptions {
+        universes: output.universe.clone(),
+        backends,
+        refresh_period: output.refresh_rate,
+        shutdown_look,
+        shutdown_fade: output.shutdown_fade,
+    }
+}
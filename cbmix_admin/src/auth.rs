@@ -0,0 +1,230 @@
+use std::future::{ready, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::rest::ApiError;
+use crate::config::{Role, TokenConfig};
+
+use axum::{
+    extract::{Query, State},
+    http::{self, header::AUTHORIZATION, Method, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+
+#[derive(Clone, Debug)]
+pub(super) struct Auth {
+    tokens: Arc<Vec<TokenConfig>>,
+}
+
+// checks gRPC calls against the same method table as WebSocket requests,
+// before they reach the service
+#[derive(Clone, Debug)]
+pub(super) struct Authorize<S> {
+    auth: Auth,
+    inner: S,
+}
+
+// browsers can't set headers on a WebSocket upgrade, so the token may also be
+// passed in the query string
+#[derive(Deserialize, Debug)]
+pub(super) struct TokenQuery {
+    token: Option<String>,
+}
+
+impl Auth {
+    pub(super) fn new(tokens: Vec<TokenConfig>) -> Self {
+        Self {
+            tokens: Arc::new(tokens),
+        }
+    }
+
+    pub(super) fn is_open(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    // the role granted to a client presenting `token`, if any
+    pub(super) fn role(&self, token: Option<&str>) -> Option<Role> {
+        if self.is_open() {
+            return Some(Role::Operator);
+        }
+
+        let token = token?;
+        self.tokens
+            .iter()
+            .filter(|t| constant_time_eq(t.token.as_bytes(), token.as_bytes()))
+            .map(|t| t.role)
+            .max()
+    }
+}
+
+pub(super) async fn authenticate<B>(
+    State(auth): State<Auth>,
+    Query(query): Query<TokenQuery>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    let header = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let role = auth
+        .role(header.or(query.token.as_deref()))
+        .ok_or_else(|| {
+            ApiError(
+                StatusCode::UNAUTHORIZED,
+                "missing or invalid token".to_string(),
+            )
+        })?;
+
+    // anything other than a read changes the graph
+    if !matches!(*request.method(), Method::GET | Method::HEAD) && role < Role::Operator {
+        return Err(ApiError(
+            StatusCode::FORBIDDEN,
+            "operator role required".to_string(),
+        ));
+    }
+
+    request.extensions_mut().insert(role);
+    Ok(next.run(request).await)
+}
+
+// the role each method of the admin protocol needs, by its name in
+// GraphService. anything not listed changes the graph
+pub(super) fn required_role(method: &str) -> Role {
+    match method {
+        "Hello" | "Subscribe" | "Unsubscribe" | "GetNode" | "GetNodes" | "GetNodeState"
+        | "GetNodeStates" | "GetInputs" | "ListSnapshots" => Role::Viewer,
+        _ => Role::Operator,
+    }
+}
+
+impl<S> Layer<S> for Auth {
+    type Service = Authorize<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Authorize {
+            auth: self.clone(),
+            inner,
+        }
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for Authorize<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "));
+        // paths are /package.Service/Method
+        let method = request.uri().path().rsplit('/').next().unwrap_or_default();
+
+        let status = match self.auth.role(token) {
+            None => Status::unauthenticated("missing or invalid token"),
+            Some(role) if role < required_role(method) => {
+                Status::permission_denied("operator role required")
+            }
+            Some(_) => return Box::pin(self.inner.call(request)),
+        };
+
+        Box::pin(ready(Ok(status.to_http())))
+    }
+}
+
+// compare without bailing out at the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::Infallible;
+
+    use cbmix_admin_proto::message::METHODS;
+    use tonic::Code;
+    use tower::{service_fn, ServiceExt};
+
+    fn auth() -> Auth {
+        Auth::new(vec![
+            TokenConfig {
+                token: "look".to_string(),
+                role: Role::Viewer,
+            },
+            TokenConfig {
+                token: "touch".to_string(),
+                role: Role::Operator,
+            },
+        ])
+    }
+
+    // the gRPC status a call gets, with OK for calls let through
+    async fn grpc_call(method: &str, token: Option<&str>) -> Code {
+        let service = auth().layer(service_fn(|_: http::Request<()>| async {
+            Ok::<_, Infallible>(Status::ok("").to_http())
+        }));
+        let mut request = http::Request::builder().uri(format!("/cbmix.GraphService/{}", method));
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        let response = service.oneshot(request.body(()).unwrap()).await.unwrap();
+        let status = response.headers().get("grpc-status").unwrap();
+        Code::from_bytes(status.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn grpc_calls_need_a_role() {
+        assert_eq!(grpc_call("GetNodes", None).await, Code::Unauthenticated);
+        assert_eq!(
+            grpc_call("GetNodes", Some("guess")).await,
+            Code::Unauthenticated
+        );
+        assert_eq!(grpc_call("GetNodes", Some("look")).await, Code::Ok);
+        assert_eq!(grpc_call("Subscribe", Some("look")).await, Code::Ok);
+
+        assert_eq!(
+            grpc_call("UpdateNode", Some("look")).await,
+            Code::PermissionDenied
+        );
+        assert_eq!(
+            grpc_call("RemoveNode", Some("look")).await,
+            Code::PermissionDenied
+        );
+        assert_eq!(grpc_call("UpdateNode", Some("touch")).await, Code::Ok);
+        assert_eq!(grpc_call("UpdateNode", None).await, Code::Unauthenticated);
+    }
+
+    #[test]
+    fn only_reads_are_open_to_viewers() {
+        let viewable: Vec<&str> = METHODS
+            .iter()
+            .copied()
+            .filter(|method| required_role(method) == Role::Viewer)
+            .collect();
+        assert!(viewable.iter().all(|method| method.starts_with("Get")
+            || method.starts_with("List")
+            || matches!(*method, "Hello" | "Subscribe" | "Unsubscribe")));
+        assert_eq!(required_role("SomethingNew"), Role::Operator);
+    }
+}
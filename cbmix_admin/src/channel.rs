@@ -1,9 +1,19 @@
 use axum::extract::ws::{Message as WsMessage, WebSocket};
-use cbmix_admin_proto::{message::Message, GraphServiceRequest};
+use cbmix_admin_proto::{json, message::Message, Error as ProtoError, GraphServiceRequest};
 use prost::Message as MessageTrait;
 use thiserror::Error;
 use tracing::{error, info, warn};
 
+// WebSocket subprotocols. clients that don't ask for one get protobuf
+pub(super) const PROTOBUF_PROTOCOL: &str = "cbmix.proto";
+pub(super) const JSON_PROTOCOL: &str = "cbmix.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Encoding {
+    Protobuf,
+    Json,
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Underlying WebSocket error")]
@@ -12,50 +22,73 @@ pub enum Error {
     UnexpectedMessage,
     #[error("Unable to decode protobuf message")]
     Decode,
+    #[error("Unable to read request {0}: {1}")]
+    Request(u32, ProtoError),
 }
 
 pub(super) async fn next(
     socket: &mut WebSocket,
 ) -> Option<Result<(u32, GraphServiceRequest), Error>> {
-    match socket.recv().await {
-        Some(Ok(msg)) => match msg {
-            WsMessage::Binary(raw) => {
-                let message = Message::decode(&*raw).map_err(|e| {
-                    error!("error parsing message: {}", e);
-                    Error::Decode
-                });
-
-                match message {
-                    Ok(m) => Some(Message::get_request(&m).map_err(|e| {
-                        error!("error reading message: {}", e);
-                        Error::Decode
-                    })),
-                    Err(e) => Some(Err(e)),
-                }
-            }
-            WsMessage::Close(_) => None,
-            _ => {
-                warn!("recieved unexpected websocket message: {:?}", msg);
-                Some(Err(Error::UnexpectedMessage))
-            }
-        },
+    let message = match socket.recv().await {
+        Some(Ok(WsMessage::Binary(raw))) => Message::decode(&*raw).map_err(|e| {
+            error!("error parsing message: {}", e);
+            Error::Decode
+        }),
+        Some(Ok(WsMessage::Text(text))) => json::from_json(&text).map_err(|e| {
+            error!("error parsing JSON message: {}", e);
+            Error::Decode
+        }),
+        Some(Ok(WsMessage::Close(_))) => return None,
+        Some(Ok(msg)) => {
+            warn!("recieved unexpected websocket message: {:?}", msg);
+            return Some(Err(Error::UnexpectedMessage));
+        }
         Some(Err(e)) => {
             error!("websocket error: {}", e);
-            Some(Err(Error::Socket))
+            return Some(Err(Error::Socket));
         }
         None => {
             info!("client has disconnected");
-            None
+            return None;
         }
-    }
-}
+    };
 
-pub(super) async fn send(socket: &mut WebSocket, message: Message) -> Result<(), Error> {
-    socket
-        .send(WsMessage::Binary(message.encode_to_vec()))
-        .await
-        .map_err(|e| {
-            error!("websocket error: {}", e);
-            Error::Socket
+    Some(message.and_then(|m| {
+        m.get_request().map_err(|e| {
+            error!("error reading message: {}", e);
+            // requests with a sequence number can still be answered
+            match m.seq {
+                Some(seq) => Error::Request(seq, e),
+                None => Error::Decode,
+            }
         })
+    }))
+}
+
+pub(super) async fn send(
+    socket: &mut WebSocket,
+    encoding: Encoding,
+    message: Message,
+) -> Result<(), Error> {
+    let frame = match encoding {
+        Encoding::Protobuf => WsMessage::Binary(message.encode_to_vec()),
+        Encoding::Json => WsMessage::Text(json::to_json(&message).map_err(|e| {
+            error!("error encoding JSON message: {}", e);
+            Error::Decode
+        })?),
+    };
+
+    socket.send(frame).await.map_err(|e| {
+        error!("websocket error: {}", e);
+        Error::Socket
+    })
+}
+
+impl Encoding {
+    pub(super) fn of(socket: &WebSocket) -> Self {
+        match socket.protocol().and_then(|p| p.to_str().ok()) {
+            Some(JSON_PROTOCOL) => Encoding::Json,
+            _ => Encoding::Protobuf,
+        }
+    }
 }
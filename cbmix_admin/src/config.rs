@@ -1,20 +1,65 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 use serde::Deserialize;
 
 pub const DEFAULT_LISTEN_ADDR: &str = "[::0]:8080";
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct AdminConfig {
     #[serde(default = "default_listen_addr")]
     pub listen_addr: SocketAddr,
+    #[serde(default)]
+    pub grpc_listen_addr: Option<SocketAddr>,
+    // with no tokens configured, every client is treated as an operator
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    // serve the web UI from this directory instead of the built-in page
+    #[serde(default)]
+    pub ui_dir: Option<PathBuf>,
+    // snapshots are saved here, one JSON file each
+    #[serde(default)]
+    pub snapshot_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // require clients to present a certificate signed by this CA
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub token: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // may read nodes and subscribe to them
+    Viewer,
+    // may also update and remove nodes
+    Operator,
 }
 
 impl Default for AdminConfig {
     fn default() -> Self {
         Self {
             listen_addr: default_listen_addr(),
+            grpc_listen_addr: None,
+            tokens: Vec::new(),
+            tls: None,
+            ui_dir: None,
+            snapshot_dir: None,
         }
     }
 }
@@ -0,0 +1,408 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::playback;
+use crate::server_name;
+use crate::snapshot::{self, Snapshots};
+
+use cbmix_admin_proto::{
+    graph_service_server::GraphService, hello, input_to_proto, levels_from_proto, node_from_proto,
+    node_to_proto, state_to_proto, ControlPlayback, CreateSnapshot, Hello, Inputs, Node, NodeId,
+    NodeRevision, NodeState, NodeStates, Nodes, RecallSnapshot, RemoveNode, SetChannels,
+    SnapshotInfo, SnapshotName, Snapshots as SnapshotList, SubscriptionId, SubscriptionUpdateEvent,
+    PROTOCOL_VERSION,
+};
+use cbmix_common::input;
+use cbmix_graph::{Error as GraphError, GraphHandle, GraphUpdate, SceneError};
+use cbmix_record::players;
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+const SUBSCRIPTION_BUFFER_SIZE: usize = 100;
+
+pub(super) struct GraphServer {
+    graph: GraphHandle,
+    inputs: input::Receiver,
+    players: players::Receiver,
+    snapshots: Snapshots,
+}
+
+// a subscription that removes itself from the graph once the client goes away
+pub(super) struct SubscriptionStream {
+    id: Uuid,
+    graph: GraphHandle,
+    updates: mpsc::Receiver<GraphUpdate>,
+}
+
+impl GraphServer {
+    pub(super) fn new(
+        graph: GraphHandle,
+        inputs: input::Receiver,
+        players: players::Receiver,
+        snapshots: Snapshots,
+    ) -> Self {
+        Self {
+            graph,
+            inputs,
+            players,
+            snapshots,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl GraphService for GraphServer {
+    type SubscribeStream = SubscriptionStream;
+
+    async fn hello(&self, request: Request<Hello>) -> Result<Response<Hello>, Status> {
+        let version = request.get_ref().protocol_version;
+        if version != 0 && version != PROTOCOL_VERSION {
+            return Err(Status::failed_precondition(format!(
+                "unsupported protocol version {}, server speaks {}",
+                version, PROTOCOL_VERSION
+            )));
+        }
+
+        Ok(Response::new(hello(&server_name())))
+    }
+
+    async fn subscribe(
+        &self,
+        request: Request<NodeId>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let node = Uuid::try_parse(&request.get_ref().id).map_err(invalid_uuid)?;
+        let (subscriber, updates) = mpsc::channel(SUBSCRIPTION_BUFFER_SIZE);
+        let id = self
+            .graph
+            .subscribe(node, subscriber)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(SubscriptionStream {
+            id,
+            graph: self.graph.clone(),
+            updates,
+        }))
+    }
+
+    async fn unsubscribe(&self, request: Request<SubscriptionId>) -> Result<Response<()>, Status> {
+        let id = Uuid::try_parse(&request.get_ref().id).map_err(invalid_uuid)?;
+        self.graph.unsubscribe(id).await.map_err(to_status)?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn get_node(&self, request: Request<NodeId>) -> Result<Response<Node>, Status> {
+        let id = Uuid::try_parse(&request.get_ref().id).map_err(invalid_uuid)?;
+        let (node, revision) = self.graph.get(id).await.map_err(to_status)?;
+
+        Ok(Response::new(node_to_proto(&id, &node, Some(revision))))
+    }
+
+    async fn get_nodes(&self, _: Request<()>) -> Result<Response<Nodes>, Status> {
+        let nodes = self.graph.list().await.map_err(to_status)?;
+
+        Ok(Response::new(Nodes {
+            nodes: nodes
+                .iter()
+                .map(|(i, n, r)| node_to_proto(i, n, Some(*r)))
+                .collect(),
+        }))
+    }
+
+    async fn get_node_state(
+        &self,
+        request: Request<NodeId>,
+    ) -> Result<Response<NodeState>, Status> {
+        let id = Uuid::try_parse(&request.get_ref().id).map_err(invalid_uuid)?;
+        let channels = self.graph.get_state(id).await.map_err(to_status)?;
+
+        Ok(Response::new(state_to_proto(&id, &channels)))
+    }
+
+    async fn get_node_states(&self, _: Request<()>) -> Result<Response<NodeStates>, Status> {
+        let states = self.graph.get_states().await.map_err(to_status)?;
+
+        Ok(Response::new(NodeStates {
+            states: states.iter().map(|(i, c)| state_to_proto(i, c)).collect(),
+        }))
+    }
+
+    async fn update_node(&self, request: Request<Node>) -> Result<Response<NodeRevision>, Status> {
+        let (id, body) = node_from_proto(request.get_ref())
+            .ok_or_else(|| Status::invalid_argument("incomplete or invalid node"))?;
+        let id = id.unwrap_or_else(Uuid::new_v4);
+        let revision = self
+            .graph
+            .insert_checked(id, body, request.get_ref().revision)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(NodeRevision {
+            id: id.to_string(),
+            revision: Some(revision),
+        }))
+    }
+
+    async fn set_channels(&self, request: Request<SetChannels>) -> Result<Response<()>, Status> {
+        let (id, levels) = levels_from_proto(request.get_ref())
+            .ok_or_else(|| Status::invalid_argument("invalid id or channel level"))?;
+        self.graph
+            .set_channels(id, levels)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn remove_node(&self, request: Request<RemoveNode>) -> Result<Response<()>, Status> {
+        let id = Uuid::try_parse(&request.get_ref().id).map_err(invalid_uuid)?;
+        self.graph
+            .remove_checked(id, request.get_ref().revision)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn undo(&self, _: Request<()>) -> Result<Response<()>, Status> {
+        self.snapshots.stop_fade().await;
+        self.graph.undo().await.map_err(to_status)?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn redo(&self, _: Request<()>) -> Result<Response<()>, Status> {
+        self.snapshots.stop_fade().await;
+        self.graph.redo().await.map_err(to_status)?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn get_inputs(&self, _: Request<()>) -> Result<Response<Inputs>, Status> {
+        Ok(Response::new(Inputs {
+            inputs: self
+                .inputs
+                .borrow()
+                .iter()
+                .map(|(i, s)| input_to_proto(i, s))
+                .collect(),
+        }))
+    }
+
+    async fn create_snapshot(
+        &self,
+        request: Request<CreateSnapshot>,
+    ) -> Result<Response<SnapshotInfo>, Status> {
+        let CreateSnapshot { name, all_nodes } = request.into_inner();
+        let info = self
+            .snapshots
+            .create(&name, all_nodes)
+            .await
+            .map_err(snapshot_status)?;
+
+        Ok(Response::new(info))
+    }
+
+    async fn list_snapshots(&self, _: Request<()>) -> Result<Response<SnapshotList>, Status> {
+        let snapshots = self.snapshots.list().await.map_err(snapshot_status)?;
+
+        Ok(Response::new(SnapshotList { snapshots }))
+    }
+
+    async fn recall_snapshot(
+        &self,
+        request: Request<RecallSnapshot>,
+    ) -> Result<Response<()>, Status> {
+        let RecallSnapshot { name, fade } = request.into_inner();
+        self.snapshots
+            .recall(&name, fade.map(|ms| Duration::from_millis(ms as u64)))
+            .await
+            .map_err(snapshot_status)?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn delete_snapshot(
+        &self,
+        request: Request<SnapshotName>,
+    ) -> Result<Response<()>, Status> {
+        self.snapshots
+            .delete(&request.get_ref().name)
+            .await
+            .map_err(snapshot_status)?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn control_playback(
+        &self,
+        request: Request<ControlPlayback>,
+    ) -> Result<Response<()>, Status> {
+        let ControlPlayback {
+            id,
+            seek,
+            speed,
+            looping,
+        } = request.into_inner();
+        let id = Uuid::try_parse(&id).map_err(invalid_uuid)?;
+        playback::control(
+            &self.players,
+            id,
+            seek.map(Duration::from_millis),
+            speed,
+            looping,
+        )
+        .await
+        .map_err(playback_status)?;
+
+        Ok(Response::new(()))
+    }
+}
+
+impl Stream for SubscriptionStream {
+    type Item = Result<SubscriptionUpdateEvent, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.updates.poll_recv(cx) {
+            Poll::Ready(Some(GraphUpdate::Update {
+                id,
+                channels,
+                revision,
+            })) => Poll::Ready(Some(Ok(SubscriptionUpdateEvent {
+                id: Some(NodeId { id: id.to_string() }),
+                channels: channels.into(),
+                revision: Some(revision),
+            }))),
+            Poll::Ready(Some(GraphUpdate::Closed { .. }) | None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for SubscriptionStream {
+    fn drop(&mut self) {
+        // closing the channel first keeps the graph from waiting on a full
+        // buffer that nobody is reading
+        self.updates.close();
+
+        let id = self.id;
+        let graph = self.graph.clone();
+        tokio::spawn(async move {
+            if let Err(e) = graph.unsubscribe(id).await {
+                debug!("subscription {} already removed: {}", id, e);
+            }
+        });
+    }
+}
+
+fn invalid_uuid(_: uuid::Error) -> Status {
+    Status::invalid_argument("failed to parse UUID")
+}
+
+fn to_status(e: GraphError) -> Status {
+    match e {
+        GraphError::MissingNode | GraphError::MissingSubscription => {
+            Status::not_found(e.to_string())
+        }
+        GraphError::Insert(SceneError::Conflict { .. })
+        | GraphError::Remove(SceneError::Conflict { .. }) => Status::aborted(e.to_string()),
+        GraphError::Insert(_)
+        | GraphError::Remove(_)
+        | GraphError::Replay(_)
+        | GraphError::Subscribe(_)
+        | GraphError::EmptyHistory => Status::failed_precondition(e.to_string()),
+        GraphError::Send(_) | GraphError::Receive(_) => {
+            error!("graph unavailable: {}", e);
+            Status::unavailable(e.to_string())
+        }
+    }
+}
+
+fn snapshot_status(e: snapshot::Error) -> Status {
+    match e {
+        snapshot::Error::Graph(e) => to_status(*e),
+        snapshot::Error::Unknown(_) => Status::not_found(e.to_string()),
+        snapshot::Error::Name => Status::invalid_argument(e.to_string()),
+        snapshot::Error::Disabled => Status::failed_precondition(e.to_string()),
+        snapshot::Error::Io(..) | snapshot::Error::Invalid(..) | snapshot::Error::Incomplete(_) => {
+            error!("{}", e);
+            Status::internal(e.to_string())
+        }
+    }
+}
+
+fn playback_status(e: playback::Error) -> Status {
+    match e {
+        playback::Error::NotPlaying(_) => Status::not_found(e.to_string()),
+        playback::Error::Player(cbmix_record::Error::Speed(_)) => {
+            Status::invalid_argument(e.to_string())
+        }
+        playback::Error::Player(cbmix_record::Error::PlayerStopped) => {
+            Status::unavailable(e.to_string())
+        }
+        playback::Error::Player(_) => {
+            error!("{}", e);
+            Status::internal(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cbmix_common::shutdown;
+    use cbmix_graph::Graph;
+    use tonic::Code;
+
+    // the shutdown sender has to outlive the graph
+    fn server() -> (GraphServer, shutdown::Sender) {
+        let shutdown = shutdown::Sender::new();
+        let graph = Graph::new(shutdown.subscribe());
+        let server = GraphServer::new(
+            graph.handle(),
+            input::channel().1,
+            players::channel().1,
+            Snapshots::new(None, graph.handle()),
+        );
+        tokio::spawn(graph.serve());
+
+        (server, shutdown)
+    }
+
+    fn input(id: Uuid, level: u8, revision: Option<u64>) -> Request<Node> {
+        let node = cbmix_graph::Node::Input {
+            channels: vec![level; 512].try_into().unwrap(),
+        };
+        Request::new(node_to_proto(&id, &node, revision))
+    }
+
+    #[tokio::test]
+    async fn stale_revisions_are_aborted() {
+        let (server, _shutdown) = server();
+        let id = Uuid::new_v4();
+
+        let first = server.update_node(input(id, 10, Some(0))).await.unwrap();
+        let first = first.get_ref().revision;
+        let second = server.update_node(input(id, 20, first)).await.unwrap();
+        let second = second.get_ref().revision;
+        assert!(second > first);
+
+        let stale = server.update_node(input(id, 30, first)).await.unwrap_err();
+        assert_eq!(stale.code(), Code::Aborted);
+        let remove = |revision| {
+            Request::new(RemoveNode {
+                id: id.to_string(),
+                revision,
+            })
+        };
+        let stale = server.remove_node(remove(first)).await.unwrap_err();
+        assert_eq!(stale.code(), Code::Aborted);
+        server.remove_node(remove(second)).await.unwrap();
+    }
+}
@@ -1,23 +1,42 @@
+mod auth;
 mod channel;
 pub mod config;
+mod grpc;
+mod playback;
+mod rest;
+mod snapshot;
+mod tls;
+mod ui;
+
+pub use tls::Error as TlsError;
 
 use std::collections::HashSet;
 
-use channel::{next, send, Error as ChannelError};
-use config::AdminConfig;
+use auth::{required_role, Auth};
+use channel::{next, send, Encoding, Error as ChannelError, JSON_PROTOCOL, PROTOBUF_PROTOCOL};
+use config::{AdminConfig, Role};
+use grpc::GraphServer;
+use snapshot::Snapshots;
 
 use axum::{
     extract::{ws::WebSocketUpgrade, State},
+    middleware::from_fn_with_state,
     response::Response,
     routing::{get, Router},
-    Server,
+    Extension, Server,
 };
 use cbmix_admin_proto::{
-    error_message, event::Event, GraphServiceRequest, GraphServiceResponse, NodeId,
-    SubscriptionUpdateEvent,
+    error_message, error_to_proto,
+    event::Event,
+    graph_service_server::GraphServiceServer,
+    hello, input_to_proto,
+    message::{ErrorCode, ErrorResponse},
+    GraphServiceRequest, GraphServiceResponse, InputStatusEvent, NodeId, SubscriptionUpdateEvent,
+    PROTOCOL_VERSION,
 };
-use cbmix_common::shutdown;
+use cbmix_common::{input, shutdown};
 use cbmix_graph::{GraphHandle, GraphUpdate};
+use cbmix_record::players;
 use thiserror::Error;
 use tokio::sync::mpsc;
 use tower::ServiceBuilder;
@@ -30,50 +49,145 @@ use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Failed to subscribe to graph node")]
-    Subscribe,
-    #[error("Failed to unsubscribe from graph node")]
-    Unsubscribe,
-    #[error("Failed to get node")]
-    Get,
-    #[error("Failed to get nodes")]
-    List,
-    #[error("Failed to update node")]
-    Update,
-    #[error("Failed to remove node")]
-    Remove,
+    #[error("{0}")]
+    Graph(Box<cbmix_graph::Error>),
+    #[error("Operator role required")]
+    Forbidden,
+    #[error("Unsupported protocol version {0}, server speaks {PROTOCOL_VERSION}")]
+    Version(u32),
     #[error("DMX universe must be 512 channels")]
     Channels(#[from] ola::TryFromBufferError),
     #[error("Unable to parse UUID")]
     Uuid(#[from] uuid::Error),
+    #[error("{0}")]
+    Snapshot(#[from] snapshot::Error),
+    #[error("{0}")]
+    Playback(#[from] playback::Error),
+}
+
+impl From<cbmix_graph::Error> for Error {
+    fn from(e: cbmix_graph::Error) -> Self {
+        // graph errors can carry a whole node back from a failed send
+        Error::Graph(Box::new(e))
+    }
+}
+
+impl Error {
+    // `node` is the node the failed request was for, if any
+    fn to_proto(&self, node: Option<Uuid>) -> ErrorResponse {
+        let code = match self {
+            Error::Graph(e) => return error_to_proto(e, node),
+            Error::Forbidden => ErrorCode::PermissionDenied,
+            Error::Version(_) => ErrorCode::UnsupportedVersion,
+            Error::Channels(_) | Error::Uuid(_) => ErrorCode::InvalidArgument,
+            Error::Snapshot(e) => match e {
+                snapshot::Error::Graph(e) => return error_to_proto(e, None),
+                snapshot::Error::Unknown(_) => ErrorCode::UnknownSnapshot,
+                snapshot::Error::Name => ErrorCode::InvalidArgument,
+                snapshot::Error::Disabled
+                | snapshot::Error::Io(..)
+                | snapshot::Error::Invalid(..)
+                | snapshot::Error::Incomplete(_) => ErrorCode::Internal,
+            },
+            Error::Playback(e) => match e {
+                playback::Error::NotPlaying(id) => {
+                    return ErrorResponse {
+                        code: ErrorCode::UnknownNode as i32,
+                        message: self.to_string(),
+                        node_id: Some(id.to_string()),
+                        input_index: None,
+                    }
+                }
+                playback::Error::Player(cbmix_record::Error::Speed(_)) => {
+                    ErrorCode::InvalidArgument
+                }
+                playback::Error::Player(cbmix_record::Error::PlayerStopped) => {
+                    ErrorCode::Unavailable
+                }
+                playback::Error::Player(_) => ErrorCode::Internal,
+            },
+        };
+
+        ErrorResponse {
+            code: code as i32,
+            message: self.to_string(),
+            node_id: None,
+            input_index: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Admin {
     config: AdminConfig,
     graph: GraphHandle,
+    inputs: input::Receiver,
+    players: players::Receiver,
+    snapshots: Snapshots,
     shutdown: shutdown::Receiver,
 }
 
 #[derive(Clone, Debug)]
 struct ServerState {
     graph: GraphHandle,
+    inputs: input::Receiver,
+    players: players::Receiver,
+    snapshots: Snapshots,
     shutdown: shutdown::Receiver,
 }
 
 impl Admin {
-    pub fn new(config: AdminConfig, graph: GraphHandle, shutdown: shutdown::Receiver) -> Self {
+    pub fn new(
+        config: AdminConfig,
+        graph: GraphHandle,
+        inputs: input::Receiver,
+        players: players::Receiver,
+        shutdown: shutdown::Receiver,
+    ) -> Self {
+        let snapshots = Snapshots::new(config.snapshot_dir.clone(), graph.clone());
+
         Self {
             config,
             graph,
+            inputs,
+            players,
+            snapshots,
             shutdown,
         }
     }
 
-    pub async fn serve(mut self) {
-        let routes = Router::new().route("/api/ws", get(ws_handler));
+    // fails if the TLS certificates can't be loaded, otherwise runs until
+    // shutdown
+    pub async fn serve(self) -> Result<(), TlsError> {
+        let auth = Auth::new(self.config.tokens.clone());
+        if auth.is_open() {
+            warn!("no admin tokens configured, all clients have operator access");
+        }
+
+        tokio::try_join!(self.clone().serve_http(auth.clone()), self.serve_grpc(auth))?;
+
+        Ok(())
+    }
+
+    async fn serve_http(mut self, auth: Auth) -> Result<(), TlsError> {
+        let routes = Router::new()
+            .route("/api/ws", get(ws_handler))
+            .route("/api/nodes", get(rest::list_nodes).post(rest::create_node))
+            .route(
+                "/api/nodes/:id",
+                get(rest::get_node)
+                    .put(rest::put_node)
+                    .delete(rest::delete_node),
+            )
+            .route("/api/nodes/:id/state", get(rest::get_node_state))
+            .route_layer(from_fn_with_state(auth, auth::authenticate))
+            // the UI itself is public, it asks for a token when connecting
+            .merge(ui::routes(self.config.ui_dir.as_deref()));
         let state = ServerState {
             graph: self.graph,
+            inputs: self.inputs,
+            players: self.players,
+            snapshots: self.snapshots,
             shutdown: self.shutdown.clone(),
         };
 
@@ -89,6 +203,10 @@ impl Admin {
             ),
         );
 
+        if let Some(tls) = self.config.tls {
+            return tls::serve(self.config.listen_addr, tls, app, self.shutdown).await;
+        }
+
         let server = Server::bind(&self.config.listen_addr)
             .serve(app.into_make_service())
             .with_graceful_shutdown(self.shutdown.recv());
@@ -100,32 +218,77 @@ impl Admin {
                 error!("server unexpectedly quit: {}", e);
             }
         };
+
+        Ok(())
+    }
+
+    async fn serve_grpc(mut self, auth: Auth) -> Result<(), TlsError> {
+        let Some(addr) = self.config.grpc_listen_addr else {
+            return Ok(());
+        };
+
+        let router =
+            tonic::transport::Server::builder()
+                .layer(auth)
+                .add_service(GraphServiceServer::new(GraphServer::new(
+                    self.graph,
+                    self.inputs,
+                    self.players,
+                    self.snapshots,
+                )));
+        if let Some(tls) = self.config.tls {
+            return tls::serve_grpc(addr, tls, router, self.shutdown).await;
+        }
+
+        let server = router.serve_with_shutdown(addr, self.shutdown.recv());
+
+        info!("listening for gRPC on {}", addr);
+        if let Err(e) = server.await {
+            error!("gRPC server unexpectedly quit: {}", e);
+        }
+
+        Ok(())
     }
 }
 
 #[axum::debug_handler(state = ServerState)]
-async fn ws_handler(State(mut state): State<ServerState>, ws: WebSocketUpgrade) -> Response {
-    ws.on_upgrade(|mut socket| async move {
+async fn ws_handler(
+    State(mut state): State<ServerState>,
+    Extension(role): Extension<Role>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.protocols([PROTOBUF_PROTOCOL, JSON_PROTOCOL])
+        .on_upgrade(move |mut socket| async move {
+        let encoding = Encoding::of(&socket);
         let (subscriber, mut subscription) = mpsc::channel(100);
         let mut subscriptions = HashSet::new();
+        // the rest of the connection still works once input status stops
+        let mut inputs_open = true;
+
+        let _ = send(&mut socket, encoding, hello(&server_name()).to_message()).await;
 
         loop {
             tokio::select! {
                 message = next(&mut socket) => match message {
                     Some(Ok((seq, request))) => {
+                        let node = request.node();
                         let message = match handle_request(
                             request,
-                            &mut state.graph,
+                            role,
+                            &mut state,
                             &subscriber,
                             &mut subscriptions,
                         )
                         .await
                         {
                             Ok(res) => res.to_message(seq),
-                            Err(e) => error_message(seq, e.to_string()),
+                            Err(e) => error_message(seq, e.to_proto(node)),
                         };
 
-                        let _ = send(&mut socket, message).await;
+                        let _ = send(&mut socket, encoding, message).await;
+                    }
+                    Some(Err(ChannelError::Request(seq, e))) => {
+                        let _ = send(&mut socket, encoding, error_message(seq, e.to_proto())).await;
                     }
                     None | Some(Err(ChannelError::Socket)) => {
                         for subscription in subscriptions.iter() {
@@ -140,15 +303,16 @@ async fn ws_handler(State(mut state): State<ServerState>, ws: WebSocketUpgrade)
                 },
                 update = subscription.recv() => match update {
                     Some(update) => match update {
-                        GraphUpdate::Update { id, channels } => {
+                        GraphUpdate::Update { id, channels, revision } => {
                             trace!("received updated universe: {} -> {:?}", id, channels);
                             let message = SubscriptionUpdateEvent {
                                 id: Some(NodeId { id: id.to_string() }),
                                 channels: channels.into(),
+                                revision: Some(revision),
                             }
                             .to_message();
 
-                            let _ = send(&mut socket, message).await;
+                            let _ = send(&mut socket, encoding, message).await;
                         }
                         GraphUpdate::Closed { id } => info!("subscription closed: {}", id),
                     },
@@ -157,6 +321,25 @@ async fn ws_handler(State(mut state): State<ServerState>, ws: WebSocketUpgrade)
                         break;
                     }
                 },
+                changed = state.inputs.changed(), if inputs_open => match changed {
+                    Ok(()) => {
+                        let message = InputStatusEvent {
+                            inputs: state
+                                .inputs
+                                .borrow()
+                                .iter()
+                                .map(|(i, s)| input_to_proto(i, s))
+                                .collect(),
+                        }
+                        .to_message();
+
+                        let _ = send(&mut socket, encoding, message).await;
+                    }
+                    Err(_) => {
+                        error!("input status channel closed unexpectedly");
+                        inputs_open = false;
+                    }
+                },
                 _ = state.shutdown.recv() => break,
             }
         }
@@ -169,15 +352,35 @@ async fn ws_handler(State(mut state): State<ServerState>, ws: WebSocketUpgrade)
 
 async fn handle_request(
     request: GraphServiceRequest,
-    graph: &mut GraphHandle,
+    role: Role,
+    state: &mut ServerState,
     subscriber: &mpsc::Sender<GraphUpdate>,
     subscriptions: &mut HashSet<Uuid>,
 ) -> Result<GraphServiceResponse, Error> {
+    if role < required_role(request.name()) {
+        return Err(Error::Forbidden);
+    }
+
+    let ServerState {
+        graph,
+        inputs,
+        players,
+        snapshots,
+        ..
+    } = state;
+
     match request {
+        GraphServiceRequest::Hello(version) => {
+            if version != 0 && version != PROTOCOL_VERSION {
+                return Err(Error::Version(version));
+            }
+
+            Ok(GraphServiceResponse::Hello(hello(&server_name())))
+        }
         GraphServiceRequest::Subscribe(id) => {
             let id = graph.subscribe(id, subscriber.clone()).await.map_err(|e| {
                 error!("failed to subscribe: {}", e);
-                Error::Subscribe
+                e
             })?;
 
             subscriptions.insert(id);
@@ -186,44 +389,142 @@ async fn handle_request(
         GraphServiceRequest::Unsubscribe(id) => {
             graph.unsubscribe(id).await.map_err(|e| {
                 error!("failed to unsubscribe: {}", e);
-                Error::Unsubscribe
+                e
             })?;
 
             subscriptions.remove(&id);
             Ok(GraphServiceResponse::Unsubscribe)
         }
         GraphServiceRequest::GetNode(id) => {
-            let node = graph.get(id).await.map_err(|e| {
+            let (node, revision) = graph.get(id).await.map_err(|e| {
                 error!("failed to get node {}: {}", id, e);
-                Error::Get
+                e
             })?;
 
-            Ok(GraphServiceResponse::GetNode(id, node))
+            Ok(GraphServiceResponse::GetNode(id, node, revision))
         }
         GraphServiceRequest::GetNodes => {
             let nodes = graph.list().await.map_err(|e| {
                 error!("failed to get nodes: {}", e);
-                Error::List
+                e
             })?;
 
             Ok(GraphServiceResponse::GetNodes(nodes))
         }
-        GraphServiceRequest::UpdateNode(id, body) => {
+        GraphServiceRequest::GetNodeState(id) => {
+            let channels = graph.get_state(id).await.map_err(|e| {
+                error!("failed to get state of node {}: {}", id, e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::GetNodeState(id, channels))
+        }
+        GraphServiceRequest::GetNodeStates => {
+            let states = graph.get_states().await.map_err(|e| {
+                error!("failed to get node states: {}", e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::GetNodeStates(states))
+        }
+        GraphServiceRequest::UpdateNode(id, body, revision) => {
             let id = id.unwrap_or_else(Uuid::new_v4);
-            graph.insert(id, body).await.map_err(|e| {
-                error!("failed to update node {}: {}", id, e);
-                Error::Update
+            let revision = graph
+                .insert_checked(id, body, revision)
+                .await
+                .map_err(|e| {
+                    error!("failed to update node {}: {}", id, e);
+                    e
+                })?;
+
+            Ok(GraphServiceResponse::UpdateNode(id, revision))
+        }
+        GraphServiceRequest::SetChannels(id, levels) => {
+            graph.set_channels(id, levels).await.map_err(|e| {
+                error!("failed to set channels of {}: {}", id, e);
+                e
             })?;
 
-            Ok(GraphServiceResponse::UpdateNode(id))
+            Ok(GraphServiceResponse::SetChannels)
         }
-        GraphServiceRequest::RemoveNode(id) => {
-            graph.remove(id).await.map_err(|e| {
+        GraphServiceRequest::RemoveNode(id, revision) => {
+            graph.remove_checked(id, revision).await.map_err(|e| {
                 error!("failed to remove node {}: {}", id, e);
-                Error::Remove
+                e
             })?;
 
             Ok(GraphServiceResponse::RemoveNode)
         }
+        GraphServiceRequest::Undo => {
+            snapshots.stop_fade().await;
+            graph.undo().await.map_err(|e| {
+                error!("failed to undo: {}", e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::Undo)
+        }
+        GraphServiceRequest::Redo => {
+            snapshots.stop_fade().await;
+            graph.redo().await.map_err(|e| {
+                error!("failed to redo: {}", e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::Redo)
+        }
+        GraphServiceRequest::GetInputs => {
+            Ok(GraphServiceResponse::GetInputs(inputs.borrow().clone()))
+        }
+        GraphServiceRequest::CreateSnapshot(name, all_nodes) => {
+            let info = snapshots.create(&name, all_nodes).await.map_err(|e| {
+                error!("failed to create snapshot {}: {}", name, e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::CreateSnapshot(info))
+        }
+        GraphServiceRequest::ListSnapshots => {
+            let list = snapshots.list().await.map_err(|e| {
+                error!("failed to list snapshots: {}", e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::ListSnapshots(list))
+        }
+        GraphServiceRequest::RecallSnapshot(name, fade) => {
+            snapshots.recall(&name, fade).await.map_err(|e| {
+                error!("failed to recall snapshot {}: {}", name, e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::RecallSnapshot)
+        }
+        GraphServiceRequest::DeleteSnapshot(name) => {
+            snapshots.delete(&name).await.map_err(|e| {
+                error!("failed to delete snapshot {}: {}", name, e);
+                e
+            })?;
+
+            Ok(GraphServiceResponse::DeleteSnapshot)
+        }
+        GraphServiceRequest::ControlPlayback(id, seek, speed, looping) => {
+            playback::control(players, id, seek, speed, looping)
+                .await
+                .map_err(|e| {
+                    error!("failed to control playback of {}: {}", id, e);
+                    e
+                })?;
+
+            Ok(GraphServiceResponse::ControlPlayback)
+        }
     }
 }
+
+// reported to clients in the Hello exchange
+fn server_name() -> String {
+    format!(
+        "cbmix {}",
+        option_env!("CARGO_PKG_VERSION").unwrap_or("(unknown version)")
+    )
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use cbmix_record::players;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Node {0} is not playing a recording")]
+    NotPlaying(Uuid),
+    #[error("{0}")]
+    Player(#[from] cbmix_record::Error),
+}
+
+// the speed goes first, since it's the only setting a player can refuse and
+// a refused request shouldn't change anything
+pub(super) async fn control(
+    players: &players::Receiver,
+    id: Uuid,
+    seek: Option<Duration>,
+    speed: Option<f64>,
+    looping: Option<bool>,
+) -> Result<(), Error> {
+    let player = players
+        .borrow()
+        .get(&id)
+        .cloned()
+        .ok_or(Error::NotPlaying(id))?;
+
+    if let Some(speed) = speed {
+        player.set_speed(speed).await?;
+    }
+    if let Some(looping) = looping {
+        player.set_looping(looping).await?;
+    }
+    if let Some(seek) = seek {
+        player.seek(seek).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,354 @@
+use super::ServerState;
+
+use axum::{
+    extract::{Path, State},
+    http::{header::IF_MATCH, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use cbmix_graph::{Error as GraphError, Node, SceneError};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub(super) struct ApiError(pub(super) StatusCode, pub(super) String);
+
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(super) enum NodeBody {
+    Input { channels: Vec<u8> },
+    Add { a: Option<Uuid>, b: Option<Uuid> },
+    Multiply { a: Option<Uuid>, b: Option<Uuid> },
+    Rewire { input: Option<Uuid>, map: Vec<u16> },
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct NodeJson {
+    id: Uuid,
+    revision: u64,
+    #[serde(flatten)]
+    body: NodeBody,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct NodeRevisionJson {
+    id: Uuid,
+    revision: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct NodeStateJson {
+    id: Uuid,
+    channels: Vec<u8>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let ApiError(status, error) = self;
+        (status, Json(ErrorBody { error })).into_response()
+    }
+}
+
+impl From<GraphError> for ApiError {
+    fn from(e: GraphError) -> Self {
+        let status = match e {
+            GraphError::MissingNode | GraphError::MissingSubscription => StatusCode::NOT_FOUND,
+            GraphError::Insert(SceneError::Conflict { .. })
+            | GraphError::Remove(SceneError::Conflict { .. }) => StatusCode::CONFLICT,
+            GraphError::EmptyHistory => StatusCode::CONFLICT,
+            GraphError::Insert(_)
+            | GraphError::Remove(_)
+            | GraphError::Replay(_)
+            | GraphError::Subscribe(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            GraphError::Send(_) | GraphError::Receive(_) => {
+                error!("graph unavailable: {}", e);
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        };
+
+        ApiError(status, e.to_string())
+    }
+}
+
+impl From<&Node> for NodeBody {
+    fn from(node: &Node) -> Self {
+        match node {
+            Node::Input { channels } => NodeBody::Input {
+                channels: channels.clone().into(),
+            },
+            Node::Add { a, b } => NodeBody::Add { a: *a, b: *b },
+            Node::Multiply { a, b } => NodeBody::Multiply { a: *a, b: *b },
+            Node::Rewire { input, map } => NodeBody::Rewire {
+                input: *input,
+                map: map.to_vec(),
+            },
+        }
+    }
+}
+
+impl TryFrom<NodeBody> for Node {
+    type Error = ApiError;
+
+    fn try_from(body: NodeBody) -> Result<Self, Self::Error> {
+        Ok(match body {
+            NodeBody::Input { channels } => Node::Input {
+                channels: channels.try_into().map_err(|_| {
+                    ApiError(
+                        StatusCode::BAD_REQUEST,
+                        "DMX universe must be 512 channels".to_string(),
+                    )
+                })?,
+            },
+            NodeBody::Add { a, b } => Node::Add { a, b },
+            NodeBody::Multiply { a, b } => Node::Multiply { a, b },
+            NodeBody::Rewire { input, map } => {
+                if map.iter().any(|i| *i >= 512) {
+                    return Err(ApiError(
+                        StatusCode::BAD_REQUEST,
+                        "rewire map entries must be less than 512".to_string(),
+                    ));
+                }
+
+                Node::Rewire {
+                    input,
+                    map: Box::new(map.try_into().map_err(|_| {
+                        ApiError(
+                            StatusCode::BAD_REQUEST,
+                            "rewire map must be 512 long".to_string(),
+                        )
+                    })?),
+                }
+            }
+        })
+    }
+}
+
+pub(super) async fn list_nodes(
+    State(state): State<ServerState>,
+) -> Result<Json<Vec<NodeJson>>, ApiError> {
+    let nodes = state.graph.list().await?;
+
+    Ok(Json(
+        nodes
+            .iter()
+            .map(|(id, node, revision)| NodeJson {
+                id: *id,
+                revision: *revision,
+                body: node.into(),
+            })
+            .collect(),
+    ))
+}
+
+pub(super) async fn create_node(
+    State(state): State<ServerState>,
+    Json(body): Json<NodeBody>,
+) -> Result<(StatusCode, Json<NodeRevisionJson>), ApiError> {
+    let id = Uuid::new_v4();
+    let revision = state
+        .graph
+        .insert_checked(id, body.try_into()?, None)
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(NodeRevisionJson { id, revision })))
+}
+
+pub(super) async fn get_node(
+    State(state): State<ServerState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<NodeJson>, ApiError> {
+    let (node, revision) = state.graph.get(id).await?;
+
+    Ok(Json(NodeJson {
+        id,
+        revision,
+        body: (&node).into(),
+    }))
+}
+
+// with an If-Match revision, fails with 409 if the node has changed since
+pub(super) async fn put_node(
+    State(state): State<ServerState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(body): Json<NodeBody>,
+) -> Result<Json<NodeRevisionJson>, ApiError> {
+    let revision = state
+        .graph
+        .insert_checked(id, body.try_into()?, expected_revision(&headers)?)
+        .await?;
+
+    Ok(Json(NodeRevisionJson { id, revision }))
+}
+
+pub(super) async fn delete_node(
+    State(state): State<ServerState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    state
+        .graph
+        .remove_checked(id, expected_revision(&headers)?)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn get_node_state(
+    State(state): State<ServerState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<NodeStateJson>, ApiError> {
+    let channels = state.graph.get_state(id).await?;
+
+    Ok(Json(NodeStateJson {
+        id,
+        channels: channels.into(),
+    }))
+}
+
+// the revision a client last saw, as a bare or quoted number. like
+// everywhere else, revision 0 means the node must not exist yet
+fn expected_revision(headers: &HeaderMap) -> Result<Option<u64>, ApiError> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Ok(None);
+    };
+
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().trim_matches('"').parse().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            ApiError(
+                StatusCode::BAD_REQUEST,
+                "If-Match must be a node revision".to_string(),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshots;
+
+    use axum::{
+        body::{Body, HttpBody},
+        http::Request,
+        routing::get,
+        Router,
+    };
+    use cbmix_common::{input, shutdown};
+    use cbmix_graph::Graph;
+    use cbmix_record::players;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    // the shutdown sender has to outlive the graph
+    fn api() -> (Router, shutdown::Sender) {
+        let shutdown = shutdown::Sender::new();
+        let graph = Graph::new(shutdown.subscribe());
+        let state = ServerState {
+            graph: graph.handle(),
+            inputs: input::channel().1,
+            players: players::channel().1,
+            snapshots: Snapshots::new(None, graph.handle()),
+            shutdown: shutdown.subscribe(),
+        };
+        tokio::spawn(graph.serve());
+
+        let router = Router::new()
+            .route("/api/nodes", get(list_nodes).post(create_node))
+            .route(
+                "/api/nodes/:id",
+                get(get_node).put(put_node).delete(delete_node),
+            )
+            .with_state(state);
+
+        (router, shutdown)
+    }
+
+    async fn call(
+        api: &Router,
+        method: &str,
+        uri: &str,
+        if_match: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(revision) = if_match {
+            request = request.header(IF_MATCH, revision);
+        }
+        let body = body.map_or_else(Body::empty, |b| Body::from(b.to_string()));
+        let response = api
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    fn input(level: u8) -> Value {
+        json!({ "type": "input", "channels": vec![level; 512] })
+    }
+
+    #[tokio::test]
+    async fn stale_revisions_conflict() {
+        let (api, _shutdown) = api();
+
+        let (status, created) = call(&api, "POST", "/api/nodes", None, Some(input(10))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uri = format!("/api/nodes/{}", created["id"].as_str().unwrap());
+        let first = created["revision"].as_u64().unwrap();
+        let (_, node) = call(&api, "GET", &uri, None, None).await;
+        assert_eq!(node["revision"].as_u64(), Some(first));
+
+        let first = format!("\"{}\"", first);
+        let (status, updated) = call(&api, "PUT", &uri, Some(&first), Some(input(20))).await;
+        assert_eq!(status, StatusCode::OK);
+        let second = updated["revision"].as_u64().unwrap().to_string();
+
+        // a second client still holding the first revision
+        let (status, _) = call(&api, "PUT", &uri, Some(&first), Some(input(30))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = call(&api, "DELETE", &uri, Some(&first), None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (_, node) = call(&api, "GET", &uri, None, None).await;
+        assert_eq!(node["channels"][0], 20);
+
+        let (status, _) = call(&api, "DELETE", &uri, Some("not a revision"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = call(&api, "DELETE", &uri, Some(&second), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn revision_zero_only_creates() {
+        let (api, _shutdown) = api();
+        let uri = format!("/api/nodes/{}", Uuid::new_v4());
+
+        let (status, _) = call(&api, "PUT", &uri, Some("0"), Some(input(10))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(&api, "PUT", &uri, Some("0"), Some(input(20))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use cbmix_admin_proto::{node_from_proto, node_to_proto, Snapshot, SnapshotInfo};
+use cbmix_graph::{GraphHandle, Node};
+use ola::DmxBuffer;
+use thiserror::Error;
+use tokio::fs;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+// about the rate DMX goes out at, so faster steps wouldn't be seen
+const FADE_STEP: Duration = Duration::from_millis(25);
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No snapshot directory is configured")]
+    Disabled,
+    #[error("Snapshot names may only use letters, numbers, spaces, dashes, underscores, and dots")]
+    Name,
+    #[error("Snapshot {0} does not exist")]
+    Unknown(String),
+    #[error("Unable to access snapshot {0}: {1}")]
+    Io(String, io::Error),
+    #[error("Snapshot {0} is not valid: {1}")]
+    Invalid(String, serde_json::Error),
+    #[error("Snapshot {0} holds an incomplete node")]
+    Incomplete(String),
+    #[error("{0}")]
+    Graph(Box<cbmix_graph::Error>),
+}
+
+impl From<cbmix_graph::Error> for Error {
+    fn from(e: cbmix_graph::Error) -> Self {
+        Error::Graph(Box::new(e))
+    }
+}
+
+// snapshots are kept as one JSON file each, named after the snapshot
+#[derive(Clone, Debug)]
+pub(super) struct Snapshots {
+    dir: Option<PathBuf>,
+    graph: GraphHandle,
+    // held for the whole of a recall, so recalls can't interleave
+    fade: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl Snapshots {
+    pub(super) fn new(dir: Option<PathBuf>, graph: GraphHandle) -> Self {
+        Self {
+            dir,
+            graph,
+            fade: Default::default(),
+        }
+    }
+
+    pub(super) async fn create(&self, name: &str, all_nodes: bool) -> Result<SnapshotInfo, Error> {
+        let path = self.path(name)?;
+        let io = |e| Error::Io(name.to_string(), e);
+
+        let mut nodes = self.graph.list().await?;
+        nodes.retain(|(_, node, _)| all_nodes || matches!(node, Node::Input { .. }));
+        nodes.sort_by_key(|(id, _, _)| *id);
+        let snapshot = Snapshot {
+            name: name.to_string(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_millis() as u64),
+            nodes: nodes
+                .iter()
+                .map(|(i, n, _)| node_to_proto(i, n, None))
+                .collect(),
+            all_nodes,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await.map_err(io)?;
+        }
+        let json = serde_json::to_vec_pretty(&snapshot).expect("serialize snapshot");
+        fs::write(&path, json).await.map_err(io)?;
+        info!("saved snapshot {} of {} nodes", name, snapshot.nodes.len());
+
+        Ok(snapshot_info(name, &snapshot))
+    }
+
+    pub(super) async fn list(&self) -> Result<Vec<SnapshotInfo>, Error> {
+        let dir = self.dir.as_ref().ok_or(Error::Disabled)?;
+        let io = |e| Error::Io(dir.display().to_string(), e);
+
+        let mut entries = match fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io(e)),
+        };
+
+        let mut snapshots = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(io)? {
+            let path = entry.path();
+            let Some(name) = snapshot_name(&path) else {
+                continue;
+            };
+
+            match self.load(name).await {
+                Ok(snapshot) => snapshots.push(snapshot_info(name, &snapshot)),
+                Err(e) => warn!("skipping snapshot {}: {}", name, e),
+            }
+        }
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(snapshots)
+    }
+
+    // a recall is one edit for undo, even with a fade. input levels are then
+    // stepped from where they are now, or from dark for new inputs, and other
+    // nodes change at the start. snapshots of every node also remove the
+    // nodes they don't hold
+    pub(super) async fn recall(&self, name: &str, fade: Option<Duration>) -> Result<(), Error> {
+        let snapshot = self.load(name).await?;
+        let nodes = snapshot
+            .nodes
+            .iter()
+            .map(|node| match node_from_proto(node) {
+                Some((Some(id), node)) => Ok((id, node)),
+                _ => Err(Error::Incomplete(name.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut running = self.fade.lock().await;
+        if let Some(task) = running.take() {
+            task.abort();
+        }
+
+        let Some(fade) = fade.filter(|f| !f.is_zero()) else {
+            self.graph
+                .insert_batch_checked(nodes, Vec::new(), snapshot.all_nodes)
+                .await?;
+            info!("recalled snapshot {}", name);
+            return Ok(());
+        };
+
+        let mut levels = Vec::new();
+        let mut held = Vec::new();
+        for (id, node) in &nodes {
+            if let Node::Input { channels } = node {
+                let from = match self.graph.get(*id).await {
+                    Ok((Node::Input { channels }, _)) => channels,
+                    _ => DmxBuffer::new(),
+                };
+                held.push((
+                    *id,
+                    Node::Input {
+                        channels: from.clone(),
+                    },
+                ));
+                levels.push((*id, Vec::from(from), Vec::from(channels.clone())));
+            }
+        }
+        self.graph
+            .insert_batch_checked(nodes, held, snapshot.all_nodes)
+            .await?;
+
+        info!("fading to snapshot {} over {:?}", name, fade);
+        *running = Some(tokio::spawn(run_fade(self.graph.clone(), levels, fade)));
+
+        Ok(())
+    }
+
+    // so a fade doesn't carry on over an undo or redo of its recall
+    pub(super) async fn stop_fade(&self) {
+        if let Some(task) = self.fade.lock().await.take() {
+            task.abort();
+        }
+    }
+
+    pub(super) async fn delete(&self, name: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(name)?).await {
+            Ok(()) => {
+                info!("deleted snapshot {}", name);
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(Error::Unknown(name.to_string())),
+            Err(e) => Err(Error::Io(name.to_string(), e)),
+        }
+    }
+
+    async fn load(&self, name: &str) -> Result<Snapshot, Error> {
+        let json = match fs::read(self.path(name)?).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(Error::Unknown(name.to_string()))
+            }
+            Err(e) => return Err(Error::Io(name.to_string(), e)),
+        };
+
+        serde_json::from_slice(&json).map_err(|e| Error::Invalid(name.to_string(), e))
+    }
+
+    // names become file names, so keep them from reaching outside the
+    // directory
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        let dir = self.dir.as_ref().ok_or(Error::Disabled)?;
+        let allowed = |c: char| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.');
+        if name.is_empty() || name.starts_with('.') || !name.chars().all(allowed) {
+            return Err(Error::Name);
+        }
+
+        Ok(dir.join(format!("{}.json", name)))
+    }
+}
+
+async fn run_fade(graph: GraphHandle, levels: Vec<(Uuid, Vec<u8>, Vec<u8>)>, fade: Duration) {
+    let start = Instant::now();
+    let mut steps = interval(FADE_STEP);
+    steps.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        steps.tick().await;
+        let progress = (start.elapsed().as_secs_f32() / fade.as_secs_f32()).min(1.0);
+
+        let nodes = levels
+            .iter()
+            .map(|(id, from, to)| {
+                let channels = from
+                    .iter()
+                    .zip(to)
+                    .map(|(a, b)| (*a as f32 + (*b as f32 - *a as f32) * progress).round() as u8)
+                    .collect::<Vec<u8>>();
+                let channels = channels.try_into().expect("keep universe size");
+
+                (*id, Node::Input { channels })
+            })
+            .collect();
+        if let Err(e) = graph.insert_batch(nodes).await {
+            warn!("stopping snapshot fade: {}", e);
+            return;
+        }
+
+        if progress >= 1.0 {
+            return;
+        }
+    }
+}
+
+fn snapshot_name(path: &Path) -> Option<&str> {
+    match path.extension() {
+        Some(extension) if extension == "json" => path.file_stem()?.to_str(),
+        _ => None,
+    }
+}
+
+fn snapshot_info(name: &str, snapshot: &Snapshot) -> SnapshotInfo {
+    SnapshotInfo {
+        name: name.to_string(),
+        created: snapshot.created,
+        node_count: snapshot.nodes.len() as u32,
+    }
+}
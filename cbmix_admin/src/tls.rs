@@ -0,0 +1,196 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::auth::Auth;
+use crate::config::TlsConfig;
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use cbmix_common::shutdown;
+use rustls::{
+    server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Router as TonicRouter;
+use tower::layer::util::{Identity, Stack};
+use tracing::{debug, error, info};
+
+type Connection = Result<TlsStream<TcpStream>, io::Error>;
+type GrpcRouter = TonicRouter<Stack<Auth, Identity>>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Unable to read {0}: {1}")]
+    File(PathBuf, io::Error),
+    #[error("No private key found in {0}")]
+    Key(PathBuf),
+    #[error("Invalid TLS configuration: {0}")]
+    Config(#[from] rustls::Error),
+}
+
+pub(super) async fn serve(
+    addr: SocketAddr,
+    config: TlsConfig,
+    app: Router,
+    mut shutdown: shutdown::Receiver,
+) -> Result<(), Error> {
+    let rustls = RustlsConfig::from_config(load(&config)?);
+
+    let handle = Handle::new();
+    let server = axum_server::bind_rustls(addr, rustls.clone())
+        .handle(handle.clone())
+        .serve(app.into_make_service());
+    tokio::pin!(server);
+
+    let mut hangup = signal(SignalKind::hangup()).expect("register a unix signal handler");
+    let mut stopping = false;
+
+    info!("listening on {} with TLS", addr);
+    loop {
+        tokio::select! {
+            result = &mut server => {
+                if let Err(e) = result {
+                    error!("server unexpectedly quit: {}", e);
+                }
+                break;
+            }
+            // new connections pick up the reloaded certificates, open ones
+            // keep what they negotiated
+            _ = hangup.recv() => match load(&config) {
+                Ok(server_config) => {
+                    rustls.reload_from_config(server_config);
+                    info!("reloaded TLS certificates");
+                }
+                Err(e) => error!("failed to reload TLS certificates, keeping the old ones: {}", e),
+            },
+            _ = shutdown.recv(), if !stopping => {
+                stopping = true;
+                handle.graceful_shutdown(None);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// tonic can't swap certificates while running, so connections are accepted
+// and encrypted here and handed to it ready to use
+pub(super) async fn serve_grpc(
+    addr: SocketAddr,
+    config: TlsConfig,
+    router: GrpcRouter,
+    mut shutdown: shutdown::Receiver,
+) -> Result<(), Error> {
+    let acceptor = TlsAcceptor::from(load(&config)?);
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("failed to listen for gRPC on {}: {}", addr, e);
+            return Ok(());
+        }
+    };
+
+    let (sender, connections) = mpsc::channel(16);
+    tokio::spawn(accept(listener, config, acceptor, sender));
+
+    info!("listening for gRPC on {} with TLS", addr);
+    let server =
+        router.serve_with_incoming_shutdown(ReceiverStream::new(connections), shutdown.recv());
+    if let Err(e) = server.await {
+        error!("gRPC server unexpectedly quit: {}", e);
+    }
+
+    Ok(())
+}
+
+// handshakes run on their own so a slow client can't hold up the rest. stops
+// once the server is gone
+async fn accept(
+    listener: TcpListener,
+    config: TlsConfig,
+    mut acceptor: TlsAcceptor,
+    connections: mpsc::Sender<Connection>,
+) {
+    let mut hangup = signal(SignalKind::hangup()).expect("register a unix signal handler");
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let acceptor = acceptor.clone();
+                    let connections = connections.clone();
+                    tokio::spawn(async move {
+                        match acceptor.accept(stream).await {
+                            Ok(stream) => {
+                                let _ = connections.send(Ok(stream)).await;
+                            }
+                            Err(e) => debug!("TLS handshake with {} failed: {}", peer, e),
+                        }
+                    });
+                }
+                Err(e) => error!("failed to accept gRPC connection: {}", e),
+            },
+            _ = hangup.recv() => match load(&config) {
+                Ok(server_config) => {
+                    acceptor = TlsAcceptor::from(server_config);
+                    info!("reloaded gRPC TLS certificates");
+                }
+                Err(e) => error!("failed to reload gRPC TLS certificates, keeping the old ones: {}", e),
+            },
+            _ = connections.closed() => return,
+        }
+    }
+}
+
+fn load(config: &TlsConfig) -> Result<Arc<ServerConfig>, Error> {
+    let certs = read_pem(&config.cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    let key = read_pem(&config.key)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(der) | Item::PKCS8Key(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| Error::Key(config.key.clone()))?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for item in read_pem(path)? {
+                if let Item::X509Certificate(der) = item {
+                    roots.add(&Certificate(der))?;
+                }
+            }
+
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key)?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>, Error> {
+    let file = File::open(path).map_err(|e| Error::File(path.to_path_buf(), e))?;
+
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| Error::File(path.to_path_buf(), e))
+}
@@ -0,0 +1,24 @@
+use std::path::Path;
+
+use super::ServerState;
+
+use axum::{
+    response::Html,
+    routing::{get, Router},
+};
+use tower_http::services::ServeDir;
+
+// a minimal page for browsing nodes, watching their levels, and setting input
+// channels, so there's something to look at without a separate frontend
+const INDEX: &str = include_str!("../ui/index.html");
+
+pub(super) fn routes(dir: Option<&Path>) -> Router<ServerState> {
+    match dir {
+        Some(dir) => Router::new().fallback_service(ServeDir::new(dir)),
+        None => Router::new().route("/", get(index)),
+    }
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX)
+}
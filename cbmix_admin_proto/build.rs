@@ -1,15 +1,93 @@
 use std::env::var;
 use std::io::Result;
 
+// fields encoded differently from serde's defaults under the proto3 JSON
+// mapping
+const JSON_FIELDS: &[(&str, &str)] = &[
+    (".cbmix.InputNode.channels", "crate::json::bytes"),
+    (
+        ".cbmix.SubscriptionUpdateEvent.channels",
+        "crate::json::bytes",
+    ),
+    (".cbmix.NodeState.channels", "crate::json::bytes"),
+    (".cbmix.InputStatus.state", "crate::json::input_state"),
+    (".cbmix.InputStatus.last_seen", "crate::json::uint64"),
+    (".cbmix.Node.revision", "crate::json::uint64"),
+    (".cbmix.NodeRevision.revision", "crate::json::uint64"),
+    (".cbmix.RemoveNode.revision", "crate::json::uint64"),
+    (".cbmix.Snapshot.created", "crate::json::uint64"),
+    (".cbmix.SnapshotInfo.created", "crate::json::uint64"),
+    (".cbmix.ControlPlayback.seek", "crate::json::uint64"),
+    (
+        ".cbmix.SubscriptionUpdateEvent.revision",
+        "crate::json::uint64",
+    ),
+    (".cbmix.message.Message.type", "crate::json::message_type"),
+    (
+        ".cbmix.message.ErrorResponse.code",
+        "crate::json::error_code",
+    ),
+];
+
+const OPTIONAL_FIELDS: &[&str] = &[
+    ".cbmix.Node.id",
+    ".cbmix.Node.revision",
+    ".cbmix.NodeRevision.revision",
+    ".cbmix.RemoveNode.revision",
+    ".cbmix.AddNode.a",
+    ".cbmix.AddNode.b",
+    ".cbmix.MultiplyNode.a",
+    ".cbmix.MultiplyNode.b",
+    ".cbmix.RewireNode.input",
+    ".cbmix.ChannelLevel.count",
+    ".cbmix.Snapshot.created",
+    ".cbmix.SnapshotInfo.created",
+    ".cbmix.RecallSnapshot.fade",
+    ".cbmix.ControlPlayback.seek",
+    ".cbmix.ControlPlayback.speed",
+    ".cbmix.ControlPlayback.looping",
+    ".cbmix.SubscriptionUpdateEvent.id",
+    ".cbmix.SubscriptionUpdateEvent.revision",
+    ".cbmix.SubscriptionCloseEvent.id",
+    ".cbmix.NodeState.id",
+    ".cbmix.InputStatus.id",
+    ".cbmix.InputStatus.last_seen",
+    ".cbmix.message.Message.seq",
+    ".cbmix.message.Message.name",
+    ".cbmix.message.ErrorResponse.node_id",
+    ".cbmix.message.ErrorResponse.input_index",
+];
+
 fn main() -> Result<()> {
-    prost_build::Config::new()
+    let mut builder = tonic_build::configure()
+        .build_client(false)
         .protoc_arg("--experimental_allow_proto3_optional")
         .out_dir(var("OUT").or(var("OUT_DIR")).unwrap())
-        .compile_protos(
-            &[
-                "proto/cbmix/graph.proto",
-                "proto/cbmix/message/message.proto",
-            ],
-            &["proto/"],
+        .message_attribute(".cbmix", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".cbmix", "#[serde(rename_all = \"camelCase\", default)]")
+        .enum_attribute(
+            ".cbmix.Node.body",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
         )
+        .enum_attribute(".cbmix.Node.body", "#[serde(rename_all = \"camelCase\")]")
+        // suffix match, so the attribute stays off the oneof variants
+        .field_attribute("cbmix.Node.body", "#[serde(flatten)]")
+        // the body is converted based on the message name, see json.rs
+        .field_attribute(".cbmix.message.Message.body", "#[serde(skip)]");
+
+    for (path, module) in JSON_FIELDS {
+        builder = builder.field_attribute(path, format!("#[serde(with = \"{}\")]", module));
+    }
+    for path in OPTIONAL_FIELDS {
+        builder =
+            builder.field_attribute(path, "#[serde(skip_serializing_if = \"Option::is_none\")]");
+    }
+
+    builder.compile(
+        &[
+            "proto/cbmix/graph.proto",
+            "proto/cbmix/message/message.proto",
+        ],
+        &["proto/"],
+    )
 }
@@ -1,10 +1,20 @@
-use crate::{node::Body, AddNode, InputNode, MultiplyNode, Node, RewireNode};
+use std::time::UNIX_EPOCH;
 
+use crate::message::{ErrorCode, ErrorResponse};
+use crate::{
+    node::Body, AddNode, ChannelLevel, InputNode, InputState, InputStatus, MultiplyNode, Node,
+    NodeId, NodeState, RewireNode, SetChannels,
+};
+
+use cbmix_common::input;
+use cbmix_graph::SceneError;
+use ola::DmxBuffer;
 use uuid::Uuid;
 
-pub fn to_proto(id: &Uuid, node: &cbmix_graph::Node) -> Node {
+pub fn node_to_proto(id: &Uuid, node: &cbmix_graph::Node, revision: Option<u64>) -> Node {
     Node {
         id: Some(id.to_string()),
+        revision,
         body: Some(match node {
             cbmix_graph::Node::Input { channels } => Body::Input(InputNode {
                 channels: channels.clone().into(),
@@ -25,7 +35,7 @@ pub fn to_proto(id: &Uuid, node: &cbmix_graph::Node) -> Node {
     }
 }
 
-pub fn from_proto(node: &Node) -> Option<(Option<Uuid>, cbmix_graph::Node)> {
+pub fn node_from_proto(node: &Node) -> Option<(Option<Uuid>, cbmix_graph::Node)> {
     if let Some(body) = node.body.clone() {
         let body = match body {
             Body::Input(InputNode { channels }) => cbmix_graph::Node::Input {
@@ -55,3 +65,108 @@ pub fn from_proto(node: &Node) -> Option<(Option<Uuid>, cbmix_graph::Node)> {
         None
     }
 }
+
+pub fn state_to_proto(id: &Uuid, channels: &DmxBuffer) -> NodeState {
+    NodeState {
+        id: Some(NodeId { id: id.to_string() }),
+        channels: channels.clone().into(),
+    }
+}
+
+pub fn state_from_proto(state: &NodeState) -> Option<(Uuid, DmxBuffer)> {
+    Some((
+        Uuid::try_parse(&state.id.as_ref()?.id).ok()?,
+        state.channels.clone().try_into().ok()?,
+    ))
+}
+
+pub fn levels_from_proto(request: &SetChannels) -> Option<(Uuid, Vec<cbmix_graph::ChannelLevel>)> {
+    let levels = request
+        .levels
+        .iter()
+        .map(|level| {
+            Some(cbmix_graph::ChannelLevel {
+                channel: level.channel,
+                count: level.count.unwrap_or(1),
+                value: level.value.try_into().ok()?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((Uuid::try_parse(&request.id).ok()?, levels))
+}
+
+pub fn levels_to_proto(id: &Uuid, levels: &[cbmix_graph::ChannelLevel]) -> SetChannels {
+    SetChannels {
+        id: id.to_string(),
+        levels: levels
+            .iter()
+            .map(|level| ChannelLevel {
+                channel: level.channel,
+                count: Some(level.count),
+                value: level.value as u32,
+            })
+            .collect(),
+    }
+}
+
+pub fn input_to_proto(id: &Uuid, status: &input::InputStatus) -> InputStatus {
+    InputStatus {
+        id: Some(NodeId { id: id.to_string() }),
+        universe: status.universe,
+        state: match status.state {
+            input::InputState::Waiting => InputState::Waiting,
+            input::InputState::Live => InputState::Live,
+            input::InputState::Lost => InputState::Lost,
+        } as i32,
+        last_seen: status
+            .last_seen
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64),
+    }
+}
+
+// `node` is the node the failed request was for, if any
+pub fn error_to_proto(error: &cbmix_graph::Error, node: Option<Uuid>) -> ErrorResponse {
+    let mut response = ErrorResponse {
+        code: ErrorCode::Internal as i32,
+        message: error.to_string(),
+        node_id: None,
+        input_index: None,
+    };
+
+    let code = match error {
+        cbmix_graph::Error::MissingNode => {
+            response.node_id = node.map(|id| id.to_string());
+            ErrorCode::UnknownNode
+        }
+        cbmix_graph::Error::MissingSubscription => ErrorCode::UnknownSubscription,
+        cbmix_graph::Error::EmptyHistory => ErrorCode::EmptyHistory,
+        cbmix_graph::Error::Insert(e)
+        | cbmix_graph::Error::Remove(e)
+        | cbmix_graph::Error::Replay(e)
+        | cbmix_graph::Error::Subscribe(e) => match e {
+            SceneError::MissingInput { index, id } => {
+                response.node_id = Some(id.to_string());
+                response.input_index = Some(*index);
+                ErrorCode::MissingInput
+            }
+            SceneError::UnknownNode => {
+                response.node_id = node.map(|id| id.to_string());
+                ErrorCode::UnknownNode
+            }
+            SceneError::UnknownSubscription => ErrorCode::UnknownSubscription,
+            SceneError::Cycle => ErrorCode::Cycle,
+            SceneError::NotInput | SceneError::Channel { .. } => ErrorCode::InvalidArgument,
+            SceneError::Conflict { .. } => {
+                response.node_id = node.map(|id| id.to_string());
+                ErrorCode::Conflict
+            }
+            SceneError::Subscribe(_) => ErrorCode::Internal,
+        },
+        cbmix_graph::Error::Send(_) | cbmix_graph::Error::Receive(_) => ErrorCode::Unavailable,
+    };
+    response.set_code(code);
+
+    response
+}
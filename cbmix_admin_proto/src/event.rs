@@ -1,5 +1,5 @@
 use crate::message::{Message, MessageType};
-use crate::SubscriptionUpdateEvent;
+use crate::{Hello, InputStatusEvent, SubscriptionUpdateEvent};
 
 use prost::Message as ProstMessage;
 
@@ -19,3 +19,11 @@ pub trait Event: ProstMessage + Sized {
 impl Event for SubscriptionUpdateEvent {
     const NAME: &'static str = "SubscriptionUpdateEvent";
 }
+
+impl Event for InputStatusEvent {
+    const NAME: &'static str = "InputStatusEvent";
+}
+
+impl Event for Hello {
+    const NAME: &'static str = "Hello";
+}
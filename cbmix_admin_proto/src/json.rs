@@ -0,0 +1,180 @@
+// proto3 JSON mapping for the text WebSocket encoding. Message bodies are
+// bytes, so they're base64 like any other bytes field.
+
+use crate::message::Message;
+use crate::Error;
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE},
+    Engine,
+};
+use serde_json::Value;
+
+pub fn to_json(message: &Message) -> Result<String, Error> {
+    let mut json = serde_json::to_value(message).map_err(|_| Error::Json)?;
+    if let Some(body) = &message.body {
+        json["body"] = Value::String(STANDARD.encode(body));
+    }
+
+    serde_json::to_string(&json).map_err(|_| Error::Json)
+}
+
+pub fn from_json(text: &str) -> Result<Message, Error> {
+    let mut json: Value = serde_json::from_str(text).map_err(|_| Error::Json)?;
+    let body = json.as_object_mut().and_then(|o| o.remove("body"));
+    let mut message: Message = serde_json::from_value(json).map_err(|_| Error::Json)?;
+
+    message.body = match body {
+        None | Some(Value::Null) => None,
+        Some(Value::String(body)) => Some(decode_base64(&body).ok_or(Error::Json)?),
+        Some(_) => return Err(Error::Json),
+    };
+
+    Ok(message)
+}
+
+// proto3 JSON accepts both base64 alphabets
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    STANDARD
+        .decode(text)
+        .or_else(|_| URL_SAFE.decode(text))
+        .ok()
+}
+
+pub(crate) mod bytes {
+    use super::decode_base64;
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        decode_base64(&text).ok_or_else(|| D::Error::custom("invalid base64"))
+    }
+}
+
+// 64 bit integers are strings so javascript doesn't lose precision
+pub(crate) mod uint64 {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Text(String),
+        Number(u64),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&value.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        match Option::<Repr>::deserialize(deserializer)? {
+            Some(Repr::Text(text)) => text.parse().map(Some).map_err(D::Error::custom),
+            Some(Repr::Number(number)) => Ok(Some(number)),
+            None => Ok(None),
+        }
+    }
+}
+
+// enums are written by value name, but either the name or number is accepted
+macro_rules! enum_name {
+    ($module:ident, $enum:ty) => {
+        pub(crate) mod $module {
+            use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Repr {
+                Name(String),
+                Number(i32),
+            }
+
+            pub fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+                match <$enum>::from_i32(*value) {
+                    Some(value) => serializer.serialize_str(value.as_str_name()),
+                    None => serializer.serialize_i32(*value),
+                }
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<i32, D::Error> {
+                match Repr::deserialize(deserializer)? {
+                    Repr::Name(name) => <$enum>::from_str_name(&name)
+                        .map(|value| value as i32)
+                        .ok_or_else(|| D::Error::custom(format!("unknown enum value {}", name))),
+                    Repr::Number(number) => Ok(number),
+                }
+            }
+        }
+    };
+}
+
+enum_name!(input_state, crate::InputState);
+enum_name!(message_type, crate::message::MessageType);
+enum_name!(error_code, crate::message::ErrorCode);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageType;
+    use crate::{GraphServiceRequest, NodeId};
+
+    use prost::Message as ProstMessage;
+    use uuid::Uuid;
+
+    #[test]
+    fn bodies_are_base64() {
+        let id = Uuid::new_v4();
+        let body = NodeId { id: id.to_string() }.encode_to_vec();
+        let text = format!(
+            r#"{{"type":"MESSAGE_TYPE_REQUEST","seq":7,"name":"GetNode","body":"{}"}}"#,
+            STANDARD.encode(&body)
+        );
+
+        let message = from_json(&text).unwrap();
+        assert_eq!(message.body.as_deref(), Some(&body[..]));
+        match message.get_request() {
+            Ok((7, GraphServiceRequest::GetNode(node))) => assert_eq!(node, id),
+            _ => panic!("expected a GetNode request"),
+        }
+
+        let json: Value = serde_json::from_str(&to_json(&message).unwrap()).unwrap();
+        assert_eq!(json["type"], "MESSAGE_TYPE_REQUEST");
+        assert_eq!(json["body"], Value::String(STANDARD.encode(&body)));
+    }
+
+    #[test]
+    fn url_safe_bodies_are_read() {
+        let body = vec![0xfb, 0xff];
+        let text = format!(
+            r#"{{"type":"MESSAGE_TYPE_EVENT","name":"Anything","body":"{}"}}"#,
+            URL_SAFE.encode(&body)
+        );
+
+        let message = from_json(&text).unwrap();
+        assert_eq!(message.r#type(), MessageType::Event);
+        assert_eq!(message.body, Some(body));
+    }
+
+    #[test]
+    fn other_bodies_are_refused() {
+        for body in [r#"{"id":"x"}"#, "[1,2]", "12", r#""not base64!""#] {
+            let text = format!(
+                r#"{{"type":"MESSAGE_TYPE_REQUEST","seq":1,"name":"GetNode","body":{}}}"#,
+                body
+            );
+            assert!(matches!(from_json(&text), Err(Error::Json)), "{}", body);
+        }
+    }
+}
@@ -1,16 +1,29 @@
 mod entity;
 pub mod event;
+pub mod json;
 pub mod message;
 
-use entity::to_proto;
-use message::{Message, MessageType};
+pub use entity::{
+    error_to_proto, input_to_proto, levels_from_proto, levels_to_proto, node_from_proto,
+    node_to_proto, state_from_proto, state_to_proto,
+};
+use message::{ErrorCode, ErrorResponse, Message, MessageType, METHODS};
 
+use std::time::Duration;
+
+use cbmix_common::input::InputStatuses;
+use ola::DmxBuffer;
 use prost::Message as ProstMessage;
 use thiserror::Error;
 use uuid::Uuid;
 
 include!(concat!(env!("OUT_DIR"), "/cbmix.rs"));
 
+// bumped only for changes old clients can't ignore, see message.proto
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const NODE_TYPES: &[&str] = &["input", "add", "multiply", "rewire"];
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Unable to decode protobuf message")]
@@ -23,63 +36,219 @@ pub enum Error {
     IncompleteEvent,
     #[error("Failed to parse UUID")]
     Uuid,
+    #[error("Unable to convert message to or from JSON")]
+    Json,
+}
+
+impl Error {
+    pub fn to_proto(&self) -> ErrorResponse {
+        let code = match self {
+            Error::UnknownMethod => ErrorCode::UnknownMethod,
+            _ => ErrorCode::InvalidArgument,
+        };
+
+        ErrorResponse {
+            code: code as i32,
+            message: self.to_string(),
+            node_id: None,
+            input_index: None,
+        }
+    }
+}
+
+pub fn hello(server: &str) -> Hello {
+    Hello {
+        protocol_version: PROTOCOL_VERSION,
+        server: server.to_string(),
+        node_types: NODE_TYPES.iter().map(|t| t.to_string()).collect(),
+        methods: METHODS.iter().map(|m| m.to_string()).collect(),
+    }
 }
 
-pub fn error_message(seq: u32, text: String) -> Message {
+pub fn error_message(seq: u32, error: ErrorResponse) -> Message {
     Message {
         r#type: MessageType::ResponseError as i32,
         seq: Some(seq),
         name: None,
-        body: Some(text.into_bytes()),
+        body: Some(error.encode_to_vec()),
     }
 }
 
 pub enum GraphServiceRequest {
+    Hello(u32),
     Subscribe(Uuid),
     Unsubscribe(Uuid),
     GetNode(Uuid),
     GetNodes,
-    UpdateNode(Option<Uuid>, cbmix_graph::Node),
-    RemoveNode(Uuid),
+    GetNodeState(Uuid),
+    GetNodeStates,
+    UpdateNode(Option<Uuid>, cbmix_graph::Node, Option<u64>),
+    SetChannels(Uuid, Vec<cbmix_graph::ChannelLevel>),
+    RemoveNode(Uuid, Option<u64>),
+    Undo,
+    Redo,
+    CreateSnapshot(String, bool),
+    ListSnapshots,
+    RecallSnapshot(String, Option<Duration>),
+    DeleteSnapshot(String),
+    // a seek, speed, and loop setting, each left alone when unset
+    ControlPlayback(Uuid, Option<Duration>, Option<f64>, Option<bool>),
+    GetInputs,
 }
 
 pub enum GraphServiceResponse {
+    Hello(Hello),
     Subscribe(Uuid),
     Unsubscribe,
-    GetNode(Uuid, cbmix_graph::Node),
-    GetNodes(Vec<(Uuid, cbmix_graph::Node)>),
-    UpdateNode(Uuid),
+    GetNode(Uuid, cbmix_graph::Node, u64),
+    GetNodes(Vec<(Uuid, cbmix_graph::Node, u64)>),
+    GetNodeState(Uuid, DmxBuffer),
+    GetNodeStates(Vec<(Uuid, DmxBuffer)>),
+    UpdateNode(Uuid, u64),
+    SetChannels,
     RemoveNode,
+    Undo,
+    Redo,
+    CreateSnapshot(SnapshotInfo),
+    ListSnapshots(Vec<SnapshotInfo>),
+    RecallSnapshot,
+    DeleteSnapshot,
+    ControlPlayback,
+    GetInputs(InputStatuses),
+}
+
+impl GraphServiceRequest {
+    // the method's name in GraphService
+    pub fn name(&self) -> &'static str {
+        match self {
+            GraphServiceRequest::Hello(_) => "Hello",
+            GraphServiceRequest::Subscribe(_) => "Subscribe",
+            GraphServiceRequest::Unsubscribe(_) => "Unsubscribe",
+            GraphServiceRequest::GetNode(_) => "GetNode",
+            GraphServiceRequest::GetNodes => "GetNodes",
+            GraphServiceRequest::GetNodeState(_) => "GetNodeState",
+            GraphServiceRequest::GetNodeStates => "GetNodeStates",
+            GraphServiceRequest::UpdateNode(..) => "UpdateNode",
+            GraphServiceRequest::SetChannels(..) => "SetChannels",
+            GraphServiceRequest::RemoveNode(..) => "RemoveNode",
+            GraphServiceRequest::Undo => "Undo",
+            GraphServiceRequest::Redo => "Redo",
+            GraphServiceRequest::CreateSnapshot(..) => "CreateSnapshot",
+            GraphServiceRequest::ListSnapshots => "ListSnapshots",
+            GraphServiceRequest::RecallSnapshot(..) => "RecallSnapshot",
+            GraphServiceRequest::DeleteSnapshot(_) => "DeleteSnapshot",
+            GraphServiceRequest::ControlPlayback(..) => "ControlPlayback",
+            GraphServiceRequest::GetInputs => "GetInputs",
+        }
+    }
+
+    // the node a request is for, so errors can name it
+    pub fn node(&self) -> Option<Uuid> {
+        match self {
+            GraphServiceRequest::Subscribe(id)
+            | GraphServiceRequest::GetNode(id)
+            | GraphServiceRequest::GetNodeState(id)
+            | GraphServiceRequest::SetChannels(id, _)
+            | GraphServiceRequest::RemoveNode(id, _)
+            | GraphServiceRequest::ControlPlayback(id, ..) => Some(*id),
+            GraphServiceRequest::UpdateNode(id, ..) => *id,
+            GraphServiceRequest::Hello(_)
+            | GraphServiceRequest::Unsubscribe(_)
+            | GraphServiceRequest::GetNodes
+            | GraphServiceRequest::GetNodeStates
+            | GraphServiceRequest::Undo
+            | GraphServiceRequest::Redo
+            | GraphServiceRequest::CreateSnapshot(..)
+            | GraphServiceRequest::ListSnapshots
+            | GraphServiceRequest::RecallSnapshot(..)
+            | GraphServiceRequest::DeleteSnapshot(_)
+            | GraphServiceRequest::GetInputs => None,
+        }
+    }
 }
 
 impl GraphServiceResponse {
     pub fn to_message(&self, seq: u32) -> Message {
         let (name, body) = match self {
+            GraphServiceResponse::Hello(hello) => ("Hello", Some(hello.encode_to_vec())),
             GraphServiceResponse::Subscribe(id) => (
                 "Subscribe",
                 Some(SubscriptionId { id: id.to_string() }.encode_to_vec()),
             ),
             GraphServiceResponse::Unsubscribe => ("Unsubscribe", None),
-            GraphServiceResponse::GetNode(id, node) => {
-                ("GetNode", Some(to_proto(id, node).encode_to_vec()))
-            }
+            GraphServiceResponse::GetNode(id, node, revision) => (
+                "GetNode",
+                Some(node_to_proto(id, node, Some(*revision)).encode_to_vec()),
+            ),
             GraphServiceResponse::GetNodes(nodes) => (
                 "GetNodes",
                 Some(
                     Nodes {
                         nodes: nodes
                             .iter()
-                            .map(|(i, n)| to_proto(i, n))
+                            .map(|(i, n, r)| node_to_proto(i, n, Some(*r)))
                             .collect::<Vec<Node>>(),
                     }
                     .encode_to_vec(),
                 ),
             ),
-            GraphServiceResponse::UpdateNode(id) => (
+            GraphServiceResponse::GetNodeState(id, channels) => (
+                "GetNodeState",
+                Some(state_to_proto(id, channels).encode_to_vec()),
+            ),
+            GraphServiceResponse::GetNodeStates(states) => (
+                "GetNodeStates",
+                Some(
+                    NodeStates {
+                        states: states
+                            .iter()
+                            .map(|(i, c)| state_to_proto(i, c))
+                            .collect::<Vec<NodeState>>(),
+                    }
+                    .encode_to_vec(),
+                ),
+            ),
+            GraphServiceResponse::UpdateNode(id, revision) => (
                 "UpdateNode",
-                Some(NodeId { id: id.to_string() }.encode_to_vec()),
+                Some(
+                    NodeRevision {
+                        id: id.to_string(),
+                        revision: Some(*revision),
+                    }
+                    .encode_to_vec(),
+                ),
             ),
+            GraphServiceResponse::SetChannels => ("SetChannels", None),
             GraphServiceResponse::RemoveNode => ("RemoveNode", None),
+            GraphServiceResponse::Undo => ("Undo", None),
+            GraphServiceResponse::Redo => ("Redo", None),
+            GraphServiceResponse::CreateSnapshot(info) => {
+                ("CreateSnapshot", Some(info.encode_to_vec()))
+            }
+            GraphServiceResponse::ListSnapshots(snapshots) => (
+                "ListSnapshots",
+                Some(
+                    Snapshots {
+                        snapshots: snapshots.clone(),
+                    }
+                    .encode_to_vec(),
+                ),
+            ),
+            GraphServiceResponse::RecallSnapshot => ("RecallSnapshot", None),
+            GraphServiceResponse::DeleteSnapshot => ("DeleteSnapshot", None),
+            GraphServiceResponse::ControlPlayback => ("ControlPlayback", None),
+            GraphServiceResponse::GetInputs(inputs) => (
+                "GetInputs",
+                Some(
+                    Inputs {
+                        inputs: inputs
+                            .iter()
+                            .map(|(i, s)| input_to_proto(i, s))
+                            .collect::<Vec<InputStatus>>(),
+                    }
+                    .encode_to_vec(),
+                ),
+            ),
         };
 
         Message {
@@ -1,11 +1,38 @@
-use crate::entity::from_proto;
-use crate::{Error, GraphServiceRequest, Node, NodeId, SubscriptionId};
+use crate::entity::{levels_from_proto, node_from_proto};
+use crate::{
+    ControlPlayback, CreateSnapshot, Error, GraphServiceRequest, Hello, Node, NodeId,
+    RecallSnapshot, RemoveNode, SetChannels, SnapshotName, SubscriptionId,
+};
+
+use std::time::Duration;
 
 use prost::Message as MessageTrait;
 use uuid::Uuid;
 
 include!(concat!(env!("OUT_DIR"), "/cbmix.message.rs"));
 
+// every request name `get_request` understands
+pub const METHODS: &[&str] = &[
+    "Hello",
+    "Subscribe",
+    "Unsubscribe",
+    "GetNode",
+    "GetNodes",
+    "GetNodeState",
+    "GetNodeStates",
+    "GetInputs",
+    "UpdateNode",
+    "SetChannels",
+    "RemoveNode",
+    "Undo",
+    "Redo",
+    "CreateSnapshot",
+    "ListSnapshots",
+    "RecallSnapshot",
+    "DeleteSnapshot",
+    "ControlPlayback",
+];
+
 impl Message {
     pub fn get_request(&self) -> Result<(u32, GraphServiceRequest), Error> {
         if self.r#type() != MessageType::Request {
@@ -14,6 +41,14 @@ impl Message {
 
         if let (Some(name), Some(seq)) = (self.name.as_ref(), self.seq) {
             match name.as_str() {
+                "Hello" => {
+                    let hello = match self.body.as_deref() {
+                        Some(body) => Hello::decode(body).map_err(|_| Error::Decode)?,
+                        None => Hello::default(),
+                    };
+
+                    Ok((seq, GraphServiceRequest::Hello(hello.protocol_version)))
+                }
                 "Subscribe" => Ok((
                     seq,
                     GraphServiceRequest::Subscribe(parse_node_id(
@@ -33,18 +68,69 @@ impl Message {
                     )?),
                 )),
                 "GetNodes" => Ok((seq, GraphServiceRequest::GetNodes)),
-                "UpdateNode" => {
-                    let (id, body) =
-                        parse_node(self.body.as_deref().ok_or(Error::IncompleteEvent)?)?;
-
-                    Ok((seq, GraphServiceRequest::UpdateNode(id, body)))
-                }
-                "RemoveNode" => Ok((
+                "GetNodeState" => Ok((
                     seq,
-                    GraphServiceRequest::RemoveNode(parse_node_id(
+                    GraphServiceRequest::GetNodeState(parse_node_id(
                         self.body.as_ref().ok_or(Error::IncompleteEvent)?,
                     )?),
                 )),
+                "GetNodeStates" => Ok((seq, GraphServiceRequest::GetNodeStates)),
+                "GetInputs" => Ok((seq, GraphServiceRequest::GetInputs)),
+                "UpdateNode" => {
+                    let (id, body, revision) =
+                        parse_node(self.body.as_deref().ok_or(Error::IncompleteEvent)?)?;
+
+                    Ok((seq, GraphServiceRequest::UpdateNode(id, body, revision)))
+                }
+                "SetChannels" => {
+                    let (id, levels) =
+                        parse_levels(self.body.as_deref().ok_or(Error::IncompleteEvent)?)?;
+
+                    Ok((seq, GraphServiceRequest::SetChannels(id, levels)))
+                }
+                "RemoveNode" => {
+                    let (id, revision) =
+                        parse_remove(self.body.as_deref().ok_or(Error::IncompleteEvent)?)?;
+
+                    Ok((seq, GraphServiceRequest::RemoveNode(id, revision)))
+                }
+                "CreateSnapshot" => {
+                    let request = decode::<CreateSnapshot>(self.body.as_deref())?;
+
+                    Ok((
+                        seq,
+                        GraphServiceRequest::CreateSnapshot(request.name, request.all_nodes),
+                    ))
+                }
+                "ListSnapshots" => Ok((seq, GraphServiceRequest::ListSnapshots)),
+                "RecallSnapshot" => {
+                    let request = decode::<RecallSnapshot>(self.body.as_deref())?;
+                    let fade = request.fade.map(|ms| Duration::from_millis(ms.into()));
+
+                    Ok((seq, GraphServiceRequest::RecallSnapshot(request.name, fade)))
+                }
+                "DeleteSnapshot" => {
+                    let request = decode::<SnapshotName>(self.body.as_deref())?;
+
+                    Ok((seq, GraphServiceRequest::DeleteSnapshot(request.name)))
+                }
+                "ControlPlayback" => {
+                    let request = decode::<ControlPlayback>(self.body.as_deref())?;
+                    let id = Uuid::try_parse(&request.id).map_err(|_| Error::Uuid)?;
+                    let seek = request.seek.map(Duration::from_millis);
+
+                    Ok((
+                        seq,
+                        GraphServiceRequest::ControlPlayback(
+                            id,
+                            seek,
+                            request.speed,
+                            request.looping,
+                        ),
+                    ))
+                }
+                "Undo" => Ok((seq, GraphServiceRequest::Undo)),
+                "Redo" => Ok((seq, GraphServiceRequest::Redo)),
                 _ => Err(Error::UnknownMethod),
             }
         } else {
@@ -53,10 +139,28 @@ impl Message {
     }
 }
 
-fn parse_node(body: &[u8]) -> Result<(Option<Uuid>, cbmix_graph::Node), Error> {
+fn decode<T: MessageTrait + Default>(body: Option<&[u8]>) -> Result<T, Error> {
+    T::decode(body.ok_or(Error::IncompleteEvent)?).map_err(|_| Error::Decode)
+}
+
+fn parse_node(body: &[u8]) -> Result<(Option<Uuid>, cbmix_graph::Node, Option<u64>), Error> {
     let node = Node::decode(body).map_err(|_| Error::Decode)?;
+    let (id, body) = node_from_proto(&node).ok_or(Error::IncompleteEvent)?;
+
+    Ok((id, body, node.revision))
+}
+
+fn parse_remove(body: &[u8]) -> Result<(Uuid, Option<u64>), Error> {
+    let request = RemoveNode::decode(body).map_err(|_| Error::Decode)?;
+    let id = Uuid::try_parse(&request.id).map_err(|_| Error::Uuid)?;
+
+    Ok((id, request.revision))
+}
+
+fn parse_levels(body: &[u8]) -> Result<(Uuid, Vec<cbmix_graph::ChannelLevel>), Error> {
+    let request = SetChannels::decode(body).map_err(|_| Error::Decode)?;
 
-    from_proto(&node).ok_or(Error::IncompleteEvent)
+    levels_from_proto(&request).ok_or(Error::IncompleteEvent)
 }
 
 fn parse_node_id(body: &[u8]) -> Result<Uuid, Error> {
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::Error;
+
+use cbmix_admin_proto::{
+    message::{ErrorResponse, Message, MessageType},
+    Hello, NodeId, SubscriptionId, SubscriptionUpdateEvent, PROTOCOL_VERSION,
+};
+use futures_util::{SinkExt, StreamExt};
+use ola::DmxBuffer;
+use prost::Message as ProstMessage;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{header, HeaderValue},
+        Message as WsMessage,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info, trace, warn};
+use uuid::Uuid;
+
+const SUBSCRIPTION_BUFFER_SIZE: usize = 100;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub(crate) enum Command {
+    Request {
+        name: &'static str,
+        body: Option<Vec<u8>>,
+        callback: oneshot::Sender<Result<Option<Vec<u8>>, Error>>,
+    },
+    Subscribe {
+        node: Uuid,
+        callback: oneshot::Sender<Result<(u64, mpsc::Receiver<DmxBuffer>), Error>>,
+    },
+    Unsubscribe {
+        id: u64,
+    },
+}
+
+// what to do with the response to each request in flight
+enum Pending {
+    Request(oneshot::Sender<Result<Option<Vec<u8>>, Error>>),
+    Subscribe {
+        node: Uuid,
+        callback: oneshot::Sender<Result<(u64, mpsc::Receiver<DmxBuffer>), Error>>,
+    },
+    Resubscribe {
+        id: u64,
+    },
+    Ignore,
+}
+
+// subscriptions are numbered locally since the server assigns a new id each
+// time one is restored after reconnecting
+struct Subscriber {
+    node: Uuid,
+    server_id: Option<Uuid>,
+    sender: mpsc::Sender<DmxBuffer>,
+}
+
+enum Exit {
+    Stopped,
+    Lost,
+}
+
+pub(crate) struct Connection {
+    url: String,
+    token: Option<String>,
+    commands: mpsc::Receiver<Command>,
+    seq: u32,
+    pending: HashMap<u32, Pending>,
+    subscribers: HashMap<u64, Subscriber>,
+    server_ids: HashMap<Uuid, u64>,
+    next_subscriber: u64,
+}
+
+impl Connection {
+    pub(crate) fn new(url: &str, token: Option<&str>, commands: mpsc::Receiver<Command>) -> Self {
+        Self {
+            url: url.to_string(),
+            token: token.map(|t| t.to_string()),
+            commands,
+            seq: 0,
+            pending: HashMap::new(),
+            subscribers: HashMap::new(),
+            server_ids: HashMap::new(),
+            next_subscriber: 0,
+        }
+    }
+
+    pub(crate) async fn serve(mut self, socket: Socket) {
+        let mut socket = Some(socket);
+        let mut delay = INITIAL_RETRY_DELAY;
+        // a deadline rather than a fresh sleep each loop, so a steady stream
+        // of rejected commands doesn't keep pushing the retry back
+        let mut retry_at = Instant::now();
+
+        loop {
+            match &mut socket {
+                Some(connected) => match self.run(connected).await {
+                    Exit::Stopped => {
+                        let _ = connected.close(None).await;
+                        return;
+                    }
+                    Exit::Lost => {
+                        warn!("lost connection to {}", self.url);
+                        self.disconnect();
+                        socket = None;
+                        retry_at = Instant::now() + delay;
+                    }
+                },
+                None => tokio::select! {
+                    _ = sleep_until(retry_at) => match connect(&self.url, self.token.as_deref()).await {
+                        Ok(mut connected) => {
+                            info!("reconnected to {}", self.url);
+                            delay = INITIAL_RETRY_DELAY;
+                            if self.resubscribe(&mut connected).await.is_ok() {
+                                socket = Some(connected);
+                            } else {
+                                retry_at = Instant::now() + delay;
+                            }
+                        }
+                        Err(e) => {
+                            debug!("failed to reconnect to {}: {}", self.url, e);
+                            delay = (delay * 2).min(MAX_RETRY_DELAY);
+                            retry_at = Instant::now() + delay;
+                        }
+                    },
+                    command = self.commands.recv() => match command {
+                        Some(command) => self.reject(command),
+                        None => return,
+                    },
+                },
+            }
+        }
+    }
+
+    async fn run(&mut self, socket: &mut Socket) -> Exit {
+        loop {
+            let result = tokio::select! {
+                command = self.commands.recv() => match command {
+                    Some(command) => self.handle_command(socket, command).await,
+                    None => return Exit::Stopped,
+                },
+                frame = socket.next() => match frame {
+                    Some(Ok(WsMessage::Binary(raw))) => self.handle_message(socket, &raw).await,
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return Exit::Lost,
+                    Some(Ok(_)) => continue,
+                },
+            };
+
+            if result.is_err() {
+                return Exit::Lost;
+            }
+        }
+    }
+
+    async fn handle_command(&mut self, socket: &mut Socket, command: Command) -> Result<(), Error> {
+        match command {
+            Command::Request {
+                name,
+                body,
+                callback,
+            } => {
+                self.send(socket, name, body, Pending::Request(callback))
+                    .await
+            }
+            Command::Subscribe { node, callback } => {
+                let body = NodeId {
+                    id: node.to_string(),
+                }
+                .encode_to_vec();
+                let pending = Pending::Subscribe { node, callback };
+                self.send(socket, "Subscribe", Some(body), pending).await
+            }
+            Command::Unsubscribe { id } => match self.remove_subscriber(id) {
+                Some(server_id) => self.unsubscribe(socket, server_id).await,
+                None => Ok(()),
+            },
+        }
+    }
+
+    async fn handle_message(&mut self, socket: &mut Socket, raw: &[u8]) -> Result<(), Error> {
+        let message = match Message::decode(raw) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("error parsing message: {}", e);
+                return Ok(());
+            }
+        };
+
+        match message.r#type() {
+            MessageType::Event => {
+                if message.name.as_deref() == Some("SubscriptionUpdateEvent") {
+                    self.handle_update(message.body.as_deref().unwrap_or_default());
+                }
+                Ok(())
+            }
+            MessageType::Response | MessageType::ResponseError => {
+                let Some(pending) = message.seq.and_then(|seq| self.pending.remove(&seq)) else {
+                    warn!("received response to unknown request {:?}", message.seq);
+                    return Ok(());
+                };
+                let result = match message.r#type() {
+                    MessageType::ResponseError => Err(server_error(message.body.as_deref())),
+                    _ => Ok(message.body),
+                };
+
+                self.handle_response(socket, pending, result).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn handle_response(
+        &mut self,
+        socket: &mut Socket,
+        pending: Pending,
+        result: Result<Option<Vec<u8>>, Error>,
+    ) -> Result<(), Error> {
+        match pending {
+            Pending::Request(callback) => {
+                let _ = callback.send(result);
+            }
+            Pending::Subscribe { node, callback } => match result.and_then(subscription_id) {
+                Ok(server_id) => {
+                    let _ = callback.send(Ok(self.add_subscriber(node, server_id)));
+                }
+                Err(e) => {
+                    let _ = callback.send(Err(e));
+                }
+            },
+            Pending::Resubscribe { id } => match result.and_then(subscription_id) {
+                Ok(server_id) => match self.subscribers.get_mut(&id) {
+                    Some(subscriber) => {
+                        subscriber.server_id = Some(server_id);
+                        self.server_ids.insert(server_id, id);
+                    }
+                    // dropped while the request was in flight
+                    None => self.unsubscribe(socket, server_id).await?,
+                },
+                // the node went away while we were disconnected, dropping the
+                // sender ends the subscriber's stream
+                Err(e) => {
+                    warn!("unable to restore subscription {}: {}", id, e);
+                    self.subscribers.remove(&id);
+                }
+            },
+            Pending::Ignore => {
+                if let Err(e) = result {
+                    debug!("ignored request failed: {}", e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_update(&mut self, body: &[u8]) {
+        let update = match SubscriptionUpdateEvent::decode(body) {
+            Ok(update) => update,
+            Err(e) => {
+                warn!("error parsing subscription update: {}", e);
+                return;
+            }
+        };
+        let Some(server_id) = update.id.and_then(|id| Uuid::try_parse(&id.id).ok()) else {
+            return;
+        };
+        let Some(subscriber) = self
+            .server_ids
+            .get(&server_id)
+            .and_then(|id| self.subscribers.get(id))
+        else {
+            return;
+        };
+        let Ok(channels) = DmxBuffer::try_from(update.channels) else {
+            warn!(
+                "received a universe that isn't 512 channels for {}",
+                subscriber.node
+            );
+            return;
+        };
+        trace!(
+            "received updated universe: {} -> {:?}",
+            subscriber.node,
+            channels
+        );
+
+        // a slow subscriber misses updates rather than holding up the rest
+        let _ = subscriber.sender.try_send(channels);
+    }
+
+    async fn send(
+        &mut self,
+        socket: &mut Socket,
+        name: &'static str,
+        body: Option<Vec<u8>>,
+        pending: Pending,
+    ) -> Result<(), Error> {
+        self.seq = self.seq.wrapping_add(1);
+        self.pending.insert(self.seq, pending);
+
+        let message = Message {
+            r#type: MessageType::Request as i32,
+            seq: Some(self.seq),
+            name: Some(name.to_string()),
+            body,
+        };
+
+        Ok(socket
+            .send(WsMessage::Binary(message.encode_to_vec()))
+            .await?)
+    }
+
+    async fn unsubscribe(&mut self, socket: &mut Socket, server_id: Uuid) -> Result<(), Error> {
+        let body = SubscriptionId {
+            id: server_id.to_string(),
+        }
+        .encode_to_vec();
+
+        self.send(socket, "Unsubscribe", Some(body), Pending::Ignore)
+            .await
+    }
+
+    fn add_subscriber(&mut self, node: Uuid, server_id: Uuid) -> (u64, mpsc::Receiver<DmxBuffer>) {
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER_SIZE);
+        let id = self.next_subscriber;
+        self.next_subscriber += 1;
+
+        self.subscribers.insert(
+            id,
+            Subscriber {
+                node,
+                server_id: Some(server_id),
+                sender,
+            },
+        );
+        self.server_ids.insert(server_id, id);
+
+        (id, receiver)
+    }
+
+    // the server subscription to drop, if it's currently subscribed
+    fn remove_subscriber(&mut self, id: u64) -> Option<Uuid> {
+        let server_id = self.subscribers.remove(&id)?.server_id?;
+        self.server_ids.remove(&server_id);
+
+        Some(server_id)
+    }
+
+    // the server drops every subscription along with the socket
+    fn disconnect(&mut self) {
+        for (_, pending) in self.pending.drain() {
+            match pending {
+                Pending::Request(callback) => {
+                    let _ = callback.send(Err(Error::Disconnected));
+                }
+                Pending::Subscribe { callback, .. } => {
+                    let _ = callback.send(Err(Error::Disconnected));
+                }
+                Pending::Resubscribe { .. } | Pending::Ignore => {}
+            }
+        }
+
+        self.server_ids.clear();
+        for subscriber in self.subscribers.values_mut() {
+            subscriber.server_id = None;
+        }
+    }
+
+    fn reject(&mut self, command: Command) {
+        match command {
+            Command::Request { callback, .. } => {
+                let _ = callback.send(Err(Error::Disconnected));
+            }
+            Command::Subscribe { callback, .. } => {
+                let _ = callback.send(Err(Error::Disconnected));
+            }
+            Command::Unsubscribe { id } => {
+                self.remove_subscriber(id);
+            }
+        }
+    }
+
+    async fn resubscribe(&mut self, socket: &mut Socket) -> Result<(), Error> {
+        let subscribers: Vec<(u64, Uuid)> = self
+            .subscribers
+            .iter()
+            .map(|(id, subscriber)| (*id, subscriber.node))
+            .collect();
+        for (id, node) in subscribers {
+            let body = NodeId {
+                id: node.to_string(),
+            }
+            .encode_to_vec();
+            self.send(socket, "Subscribe", Some(body), Pending::Resubscribe { id })
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+// open the socket and wait for the server to introduce itself
+pub(crate) async fn connect(url: &str, token: Option<&str>) -> Result<Socket, Error> {
+    let mut request = url.into_client_request()?;
+    request.headers_mut().insert(
+        header::SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static("cbmix.proto"),
+    );
+    if let Some(token) = token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(tokio_tungstenite::tungstenite::Error::from)?;
+        request.headers_mut().insert(header::AUTHORIZATION, value);
+    }
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+    loop {
+        match socket.next().await {
+            Some(Ok(WsMessage::Binary(raw))) => {
+                let message =
+                    Message::decode(&*raw).map_err(|_| cbmix_admin_proto::Error::Decode)?;
+                if message.r#type() != MessageType::Event
+                    || message.name.as_deref() != Some("Hello")
+                {
+                    continue;
+                }
+
+                let hello = Hello::decode(message.body.as_deref().unwrap_or_default())
+                    .map_err(|_| cbmix_admin_proto::Error::Decode)?;
+                if hello.protocol_version != PROTOCOL_VERSION {
+                    return Err(Error::Version(hello.protocol_version));
+                }
+
+                debug!("connected to {}", hello.server);
+                return Ok(socket);
+            }
+            Some(Ok(WsMessage::Close(_))) | None => return Err(Error::Disconnected),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.into()),
+        }
+    }
+}
+
+fn server_error(body: Option<&[u8]>) -> Error {
+    match ErrorResponse::decode(body.unwrap_or_default()) {
+        Ok(error) => Error::Server(error),
+        Err(_) => cbmix_admin_proto::Error::Decode.into(),
+    }
+}
+
+fn subscription_id(body: Option<Vec<u8>>) -> Result<Uuid, Error> {
+    let body = body.ok_or(cbmix_admin_proto::Error::IncompleteEvent)?;
+    let id = SubscriptionId::decode(&*body).map_err(|_| cbmix_admin_proto::Error::Decode)?;
+
+    Ok(Uuid::try_parse(&id.id).map_err(|_| cbmix_admin_proto::Error::Uuid)?)
+}
@@ -0,0 +1,313 @@
+mod connection;
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use connection::{connect, Command, Connection};
+
+use cbmix_admin_proto::{
+    levels_to_proto, message::ErrorResponse, node_from_proto, node_to_proto, state_from_proto,
+    ControlPlayback, CreateSnapshot, Hello, NodeId, NodeRevision, NodeState, NodeStates, Nodes,
+    RecallSnapshot, RemoveNode, SnapshotInfo, SnapshotName, Snapshots, PROTOCOL_VERSION,
+};
+use cbmix_graph::{ChannelLevel, Node};
+use ola::DmxBuffer;
+use prost::Message as ProstMessage;
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::Stream;
+use uuid::Uuid;
+
+const COMMAND_BUFFER_SIZE: usize = 30;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("WebSocket error: {0}")]
+    Socket(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("Server speaks protocol version {0}, expected {PROTOCOL_VERSION}")]
+    Version(u32),
+    #[error("Lost connection to the server")]
+    Disconnected,
+    #[error("Client connection task stopped")]
+    Closed,
+    #[error("{}", .0.message)]
+    Server(ErrorResponse),
+    #[error("Invalid response: {0}")]
+    Proto(#[from] cbmix_admin_proto::Error),
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::Socket(Box::new(e))
+    }
+}
+
+// a connection to a cbmix admin server. requests made while the connection
+// is down fail with `Error::Disconnected`, but the client keeps reconnecting
+// in the background and restores any open subscriptions once it's back
+#[derive(Clone, Debug)]
+pub struct Client {
+    commands: mpsc::Sender<Command>,
+}
+
+// live levels of a node. the stream ends once the client stops, or if the
+// node is gone by the time the subscription is restored after reconnecting
+#[derive(Debug)]
+pub struct Subscription {
+    node: Uuid,
+    id: u64,
+    commands: mpsc::Sender<Command>,
+    updates: mpsc::Receiver<DmxBuffer>,
+}
+
+impl Client {
+    // `url` is the admin WebSocket endpoint, like ws://localhost:8080/api/ws
+    pub async fn connect(url: &str, token: Option<&str>) -> Result<Self, Error> {
+        let socket = connect(url, token).await?;
+        let (commands, commands_rx) = mpsc::channel(COMMAND_BUFFER_SIZE);
+
+        let connection = Connection::new(url, token, commands_rx);
+        tokio::spawn(connection.serve(socket));
+
+        Ok(Self { commands })
+    }
+
+    pub async fn hello(&self) -> Result<Hello, Error> {
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            ..Default::default()
+        };
+        let body = self.request("Hello", Some(hello.encode_to_vec())).await?;
+
+        decode(body)
+    }
+
+    pub async fn insert(&self, id: Uuid, node: Node) -> Result<(), Error> {
+        self.insert_checked(id, node, None).await?;
+
+        Ok(())
+    }
+
+    // fails with a conflict unless the node is still at `revision`, returning
+    // the node's new revision
+    pub async fn insert_checked(
+        &self,
+        id: Uuid,
+        node: Node,
+        revision: Option<u64>,
+    ) -> Result<u64, Error> {
+        let body = node_to_proto(&id, &node, revision).encode_to_vec();
+        let response: NodeRevision = decode(self.request("UpdateNode", Some(body)).await?)?;
+
+        Ok(response.revision.unwrap_or_default())
+    }
+
+    pub async fn set_channels(&self, id: Uuid, levels: Vec<ChannelLevel>) -> Result<(), Error> {
+        let body = levels_to_proto(&id, &levels).encode_to_vec();
+        self.request("SetChannels", Some(body)).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(&self, id: Uuid) -> Result<(), Error> {
+        self.remove_checked(id, None).await
+    }
+
+    pub async fn remove_checked(&self, id: Uuid, revision: Option<u64>) -> Result<(), Error> {
+        let body = RemoveNode {
+            id: id.to_string(),
+            revision,
+        }
+        .encode_to_vec();
+        self.request("RemoveNode", Some(body)).await?;
+
+        Ok(())
+    }
+
+    pub async fn undo(&self) -> Result<(), Error> {
+        self.request("Undo", None).await?;
+
+        Ok(())
+    }
+
+    pub async fn redo(&self) -> Result<(), Error> {
+        self.request("Redo", None).await?;
+
+        Ok(())
+    }
+
+    pub async fn create_snapshot(
+        &self,
+        name: &str,
+        all_nodes: bool,
+    ) -> Result<SnapshotInfo, Error> {
+        let body = CreateSnapshot {
+            name: name.to_string(),
+            all_nodes,
+        }
+        .encode_to_vec();
+
+        decode(self.request("CreateSnapshot", Some(body)).await?)
+    }
+
+    pub async fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>, Error> {
+        let snapshots: Snapshots = decode(self.request("ListSnapshots", None).await?)?;
+
+        Ok(snapshots.snapshots)
+    }
+
+    pub async fn recall_snapshot(&self, name: &str, fade: Option<Duration>) -> Result<(), Error> {
+        let body = RecallSnapshot {
+            name: name.to_string(),
+            fade: fade.map(|f| f.as_millis() as u32),
+        }
+        .encode_to_vec();
+        self.request("RecallSnapshot", Some(body)).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_snapshot(&self, name: &str) -> Result<(), Error> {
+        let body = SnapshotName {
+            name: name.to_string(),
+        }
+        .encode_to_vec();
+        self.request("DeleteSnapshot", Some(body)).await?;
+
+        Ok(())
+    }
+
+    // settings left as `None` are kept
+    pub async fn control_playback(
+        &self,
+        id: Uuid,
+        seek: Option<Duration>,
+        speed: Option<f64>,
+        looping: Option<bool>,
+    ) -> Result<(), Error> {
+        let body = ControlPlayback {
+            id: id.to_string(),
+            seek: seek.map(|s| s.as_millis() as u64),
+            speed,
+            looping,
+        }
+        .encode_to_vec();
+        self.request("ControlPlayback", Some(body)).await?;
+
+        Ok(())
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<(Node, u64), Error> {
+        let body = NodeId { id: id.to_string() }.encode_to_vec();
+        let node: cbmix_admin_proto::Node = decode(self.request("GetNode", Some(body)).await?)?;
+        let (_, body) = node_from_proto(&node).ok_or(cbmix_admin_proto::Error::IncompleteEvent)?;
+
+        Ok((body, node.revision.unwrap_or_default()))
+    }
+
+    pub async fn list(&self) -> Result<Vec<(Uuid, Node, u64)>, Error> {
+        let nodes: Nodes = decode(self.request("GetNodes", None).await?)?;
+
+        nodes
+            .nodes
+            .iter()
+            .map(|node| match node_from_proto(node) {
+                Some((Some(id), body)) => Ok((id, body, node.revision.unwrap_or_default())),
+                _ => Err(cbmix_admin_proto::Error::IncompleteEvent.into()),
+            })
+            .collect()
+    }
+
+    pub async fn get_state(&self, id: Uuid) -> Result<DmxBuffer, Error> {
+        let body = NodeId { id: id.to_string() }.encode_to_vec();
+        let state: NodeState = decode(self.request("GetNodeState", Some(body)).await?)?;
+        let (_, channels) =
+            state_from_proto(&state).ok_or(cbmix_admin_proto::Error::IncompleteEvent)?;
+
+        Ok(channels)
+    }
+
+    pub async fn get_states(&self) -> Result<Vec<(Uuid, DmxBuffer)>, Error> {
+        let states: NodeStates = decode(self.request("GetNodeStates", None).await?)?;
+
+        states
+            .states
+            .iter()
+            .map(|state| {
+                state_from_proto(state)
+                    .ok_or_else(|| cbmix_admin_proto::Error::IncompleteEvent.into())
+            })
+            .collect()
+    }
+
+    pub async fn subscribe(&self, node: Uuid) -> Result<Subscription, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .send(Command::Subscribe { node, callback: tx })
+            .await
+            .map_err(|_| Error::Closed)?;
+        let (id, updates) = rx.await.map_err(|_| Error::Closed)??;
+
+        Ok(Subscription {
+            node,
+            id,
+            commands: self.commands.clone(),
+            updates,
+        })
+    }
+
+    async fn request(
+        &self,
+        name: &'static str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .send(Command::Request {
+                name,
+                body,
+                callback: tx,
+            })
+            .await
+            .map_err(|_| Error::Closed)?;
+
+        rx.await.map_err(|_| Error::Closed)?
+    }
+}
+
+impl Subscription {
+    pub fn node(&self) -> Uuid {
+        self.node
+    }
+
+    pub async fn recv(&mut self) -> Option<DmxBuffer> {
+        self.updates.recv().await
+    }
+}
+
+impl Stream for Subscription {
+    type Item = DmxBuffer;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.updates.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.updates.close();
+
+        let id = self.id;
+        let commands = self.commands.clone();
+        tokio::spawn(async move {
+            let _ = commands.send(Command::Unsubscribe { id }).await;
+        });
+    }
+}
+
+fn decode<T: ProstMessage + Default>(body: Option<Vec<u8>>) -> Result<T, Error> {
+    let body = body.ok_or(cbmix_admin_proto::Error::IncompleteEvent)?;
+
+    Ok(T::decode(&*body).map_err(|_| cbmix_admin_proto::Error::Decode)?)
+}
@@ -0,0 +1,481 @@
+use std::env::temp_dir;
+use std::fs::{remove_dir_all, remove_file, write};
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use cbmix_admin::{config::AdminConfig, Admin};
+use cbmix_admin_proto::message::ErrorCode;
+use cbmix_client::{Client, Error, Subscription};
+use cbmix_common::{input, shutdown};
+use cbmix_graph::{ChannelLevel, Graph, GraphHandle, Node};
+use cbmix_record::format::{Frame, Header, Record, Stream};
+use cbmix_record::{players, PlaybackOptions, Player};
+use ola::DmxBuffer;
+use tokio::io::copy_bidirectional;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// a graph and admin server running in this process. the senders are held so
+// the server doesn't see its inputs or shutdown channel close
+struct Server {
+    addr: SocketAddr,
+    snapshots: PathBuf,
+    graph: GraphHandle,
+    players: players::Sender,
+    _inputs: input::Sender,
+    shutdown: shutdown::Sender,
+}
+
+// forwards connections to the server until they're cut
+struct Proxy {
+    addr: SocketAddr,
+    connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Server {
+    async fn start() -> Self {
+        let addr = StdTcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .unwrap();
+
+        let shutdown = shutdown::Sender::new();
+        let graph = Graph::new(shutdown.subscribe());
+        let (inputs, inputs_rx) = input::channel();
+        let (players, players_rx) = players::channel();
+        let snapshots = temp_dir().join(format!("cbmix-{}", Uuid::new_v4()));
+        let config = AdminConfig {
+            listen_addr: addr,
+            snapshot_dir: Some(snapshots.clone()),
+            ..Default::default()
+        };
+        let admin = Admin::new(
+            config,
+            graph.handle(),
+            inputs_rx,
+            players_rx,
+            shutdown.subscribe(),
+        );
+        let handle = graph.handle();
+        tokio::spawn(graph.serve());
+        tokio::spawn(admin.serve());
+
+        Self {
+            addr,
+            snapshots,
+            graph: handle,
+            players,
+            _inputs: inputs,
+            shutdown,
+        }
+    }
+
+    // play a recording of `frames` into node `id`, controllable over the
+    // admin protocol
+    async fn play(&self, id: Uuid, frames: &[(u64, u8)]) {
+        let path = temp_dir().join(format!("cbmix-{}.cbrec", Uuid::new_v4()));
+        let mut file = Vec::new();
+        Header {
+            streams: vec![Stream {
+                id,
+                name: "test".to_string(),
+            }],
+        }
+        .encode(&mut file);
+        for (seconds, level) in frames {
+            Record {
+                time: Duration::from_secs(*seconds),
+                stream: 0,
+                frame: Frame::Full(Box::new(universe(*level))),
+            }
+            .encode(&mut file);
+        }
+        write(&path, file).unwrap();
+
+        let player = Player::open(
+            &path,
+            None,
+            id,
+            PlaybackOptions::default(),
+            self.graph.clone(),
+            self.shutdown.subscribe(),
+        )
+        .await
+        .unwrap();
+        let _ = remove_file(&path);
+
+        self.players.send_modify(|players| {
+            players.insert(id, player.handle());
+        });
+        tokio::spawn(player.serve());
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Client {
+        let url = format!("ws://{}/api/ws", addr);
+        timeout(TIMEOUT, async {
+            loop {
+                match Client::connect(&url, None).await {
+                    Ok(client) => return client,
+                    Err(_) => sleep(Duration::from_millis(20)).await,
+                }
+            }
+        })
+        .await
+        .expect("connect to admin server")
+    }
+}
+
+impl Proxy {
+    async fn start(target: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let connection = tokio::spawn(async move {
+                    if let Ok(mut server) = TcpStream::connect(target).await {
+                        let _ = copy_bidirectional(&mut client, &mut server).await;
+                    }
+                });
+                accepted.lock().unwrap().push(connection);
+            }
+        });
+
+        Self { addr, connections }
+    }
+
+    fn cut(&self) {
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.snapshots);
+    }
+}
+
+fn input(level: u8) -> Node {
+    Node::Input {
+        channels: universe(level),
+    }
+}
+
+fn universe(level: u8) -> DmxBuffer {
+    vec![level; 512].try_into().unwrap()
+}
+
+// skip updates until one shows `level`, since the first update is whatever
+// state the node was in when subscribed
+async fn wait_for(subscription: &mut Subscription, level: u8) {
+    timeout(TIMEOUT, async {
+        while let Some(channels) = subscription.next().await {
+            if Vec::from(channels)[0] == level {
+                return;
+            }
+        }
+        panic!("subscription ended before reaching {}", level);
+    })
+    .await
+    .expect("receive subscription update");
+}
+
+fn conflict<T>(result: Result<T, Error>, id: Uuid) {
+    match result {
+        Err(Error::Server(e)) => {
+            assert_eq!(e.code, ErrorCode::Conflict as i32);
+            assert_eq!(e.node_id, Some(id.to_string()));
+        }
+        result => panic!("expected a conflict, got {:?}", result.err()),
+    }
+}
+
+#[tokio::test]
+async fn requests_mirror_graph_handle() {
+    let server = Server::start().await;
+    let client = server.connect(server.addr).await;
+    let id = Uuid::new_v4();
+
+    client.insert(id, input(10)).await.unwrap();
+    match client.get(id).await.unwrap() {
+        (Node::Input { channels }, _) => assert_eq!(Vec::from(channels), vec![10; 512]),
+        node => panic!("expected an input node, got {:?}", node),
+    }
+    assert!(client
+        .list()
+        .await
+        .unwrap()
+        .iter()
+        .any(|(i, _, _)| *i == id));
+
+    let levels = vec![ChannelLevel {
+        channel: 1,
+        count: 2,
+        value: 255,
+    }];
+    client.set_channels(id, levels).await.unwrap();
+    match client.get(id).await.unwrap() {
+        (Node::Input { channels }, _) => assert_eq!(Vec::from(channels)[..4], [10, 255, 255, 10]),
+        node => panic!("expected an input node, got {:?}", node),
+    }
+    assert_eq!(
+        Vec::from(client.get_state(id).await.unwrap())[..2],
+        [10, 255]
+    );
+    let states = client.get_states().await.unwrap();
+    assert!(states.iter().any(|(i, _)| *i == id));
+
+    client.remove(id).await.unwrap();
+    match client.get(id).await {
+        Err(Error::Server(e)) => {
+            assert_eq!(e.code, ErrorCode::UnknownNode as i32);
+            assert_eq!(e.node_id, Some(id.to_string()));
+        }
+        result => panic!("expected an unknown node error, got {:?}", result),
+    }
+    match client.get_state(id).await {
+        Err(Error::Server(e)) => assert_eq!(e.code, ErrorCode::UnknownNode as i32),
+        result => panic!("expected an unknown node error, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn revisions_catch_conflicting_edits() {
+    let server = Server::start().await;
+    let client = server.connect(server.addr).await;
+    let id = Uuid::new_v4();
+    // revision 0 only matches a node that doesn't exist yet
+    let first = client.insert_checked(id, input(10), Some(0)).await.unwrap();
+    conflict(client.insert_checked(id, input(20), Some(0)).await, id);
+    assert_eq!(client.get(id).await.unwrap().1, first);
+
+    // writing the same node back doesn't count as a change
+    let unchanged = client.insert_checked(id, input(10), Some(first)).await;
+    assert_eq!(unchanged.unwrap(), first);
+
+    let second = client
+        .insert_checked(id, input(20), Some(first))
+        .await
+        .unwrap();
+    assert!(second > first);
+    conflict(client.insert_checked(id, input(30), Some(first)).await, id);
+    conflict(client.remove_checked(id, Some(first)).await, id);
+    client.remove_checked(id, Some(second)).await.unwrap();
+}
+
+#[tokio::test]
+async fn undo_restores_removed_links() {
+    let server = Server::start().await;
+    let client = server.connect(server.addr).await;
+    let (a, b, sum) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let add = Node::Add {
+        a: Some(a),
+        b: Some(b),
+    };
+
+    client.insert(a, input(10)).await.unwrap();
+    client.insert(b, input(20)).await.unwrap();
+    client.insert(sum, add.clone()).await.unwrap();
+    client.remove(a).await.unwrap();
+    assert_eq!(
+        client.get(sum).await.unwrap().0,
+        Node::Add {
+            a: None,
+            b: Some(b)
+        }
+    );
+
+    // the removal comes back along with the link it broke
+    client.undo().await.unwrap();
+    assert_eq!(client.get(sum).await.unwrap().0, add);
+    assert_eq!(Vec::from(client.get_state(sum).await.unwrap())[0], 30);
+
+    client.redo().await.unwrap();
+    assert!(client.get(a).await.is_err());
+    client.undo().await.unwrap();
+
+    client.undo().await.unwrap();
+    client.undo().await.unwrap();
+    client.undo().await.unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+    match client.undo().await {
+        Err(Error::Server(e)) => assert_eq!(e.code, ErrorCode::EmptyHistory as i32),
+        result => panic!("expected an empty history, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn snapshots_bring_back_levels() {
+    let server = Server::start().await;
+    let client = server.connect(server.addr).await;
+    let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+    client.insert(a, input(10)).await.unwrap();
+    client.insert(b, input(20)).await.unwrap();
+    let info = client.create_snapshot("look 1", false).await.unwrap();
+    assert_eq!(info.node_count, 2);
+
+    client.insert(a, input(100)).await.unwrap();
+    client.remove(b).await.unwrap();
+    client.recall_snapshot("look 1", None).await.unwrap();
+    assert_eq!(client.get(a).await.unwrap().0, input(10));
+    assert_eq!(client.get(b).await.unwrap().0, input(20));
+
+    // a fade starts from the current levels and ends on the snapshot's
+    client.insert(a, input(110)).await.unwrap();
+    let mut subscription = client.subscribe(a).await.unwrap();
+    let fade = Duration::from_millis(200);
+    client.recall_snapshot("look 1", Some(fade)).await.unwrap();
+    wait_for(&mut subscription, 10).await;
+
+    let snapshots = client.list_snapshots().await.unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].name, "look 1");
+
+    client.delete_snapshot("look 1").await.unwrap();
+    match client.recall_snapshot("look 1", None).await {
+        Err(Error::Server(e)) => assert_eq!(e.code, ErrorCode::UnknownSnapshot as i32),
+        result => panic!("expected an unknown snapshot error, got {:?}", result),
+    }
+    match client.create_snapshot("../look", false).await {
+        Err(Error::Server(e)) => assert_eq!(e.code, ErrorCode::InvalidArgument as i32),
+        result => panic!("expected an invalid argument error, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn snapshot_recall_is_one_edit() {
+    let server = Server::start().await;
+    let client = server.connect(server.addr).await;
+    let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+    client.insert(a, input(10)).await.unwrap();
+    client.insert(b, input(20)).await.unwrap();
+    client.create_snapshot("everything", true).await.unwrap();
+
+    // a snapshot of every node also takes away nodes added since
+    client.insert(a, input(100)).await.unwrap();
+    client.insert(c, input(30)).await.unwrap();
+    client.recall_snapshot("everything", None).await.unwrap();
+    assert_eq!(client.get(a).await.unwrap().0, input(10));
+    assert!(client.get(c).await.is_err());
+
+    client.undo().await.unwrap();
+    assert_eq!(client.get(a).await.unwrap().0, input(100));
+    assert_eq!(client.get(c).await.unwrap().0, input(30));
+    client.redo().await.unwrap();
+    assert!(client.get(c).await.is_err());
+
+    // undoing a fade stops it, and redoing lands on the snapshot's levels
+    client.insert(a, input(200)).await.unwrap();
+    let fade = Duration::from_secs(10);
+    client
+        .recall_snapshot("everything", Some(fade))
+        .await
+        .unwrap();
+    client.undo().await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.get(a).await.unwrap().0, input(200));
+    client.redo().await.unwrap();
+    assert_eq!(client.get(a).await.unwrap().0, input(10));
+}
+
+#[tokio::test]
+async fn playback_controls_reach_players() {
+    let server = Server::start().await;
+    let client = server.connect(server.addr).await;
+    let id = Uuid::new_v4();
+
+    server.play(id, &[(0, 10), (600, 20), (1200, 30)]).await;
+    let mut subscription = client.subscribe(id).await.unwrap();
+    wait_for(&mut subscription, 10).await;
+
+    client
+        .control_playback(id, Some(Duration::from_secs(900)), None, None)
+        .await
+        .unwrap();
+    wait_for(&mut subscription, 20).await;
+
+    // at this speed the last frame is 30ms away
+    client
+        .control_playback(id, None, Some(10_000.0), Some(false))
+        .await
+        .unwrap();
+    wait_for(&mut subscription, 30).await;
+
+    match client.control_playback(id, None, Some(-1.0), None).await {
+        Err(Error::Server(e)) => assert_eq!(e.code, ErrorCode::InvalidArgument as i32),
+        result => panic!("expected an invalid argument error, got {:?}", result),
+    }
+
+    let other = Uuid::new_v4();
+    client.insert(other, input(10)).await.unwrap();
+    match client.control_playback(other, None, None, Some(true)).await {
+        Err(Error::Server(e)) => {
+            assert_eq!(e.code, ErrorCode::UnknownNode as i32);
+            assert_eq!(e.node_id, Some(other.to_string()));
+        }
+        result => panic!("expected an unknown node error, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn subscriptions_follow_updates() {
+    let server = Server::start().await;
+    let client = server.connect(server.addr).await;
+    let id = Uuid::new_v4();
+
+    client.insert(id, input(10)).await.unwrap();
+    let mut first = client.subscribe(id).await.unwrap();
+    let mut second = client.subscribe(id).await.unwrap();
+    wait_for(&mut first, 10).await;
+    wait_for(&mut second, 10).await;
+
+    client.insert(id, input(20)).await.unwrap();
+    wait_for(&mut first, 20).await;
+    wait_for(&mut second, 20).await;
+
+    // dropping one subscriber leaves the other subscribed
+    drop(first);
+    client.insert(id, input(30)).await.unwrap();
+    wait_for(&mut second, 30).await;
+
+    match client.subscribe(Uuid::new_v4()).await {
+        Err(Error::Server(e)) => assert_eq!(e.code, ErrorCode::UnknownNode as i32),
+        result => panic!("expected an unknown node error, got {:?}", result.err()),
+    }
+}
+
+#[tokio::test]
+async fn reconnects_and_resubscribes() {
+    let server = Server::start().await;
+    let proxy = Proxy::start(server.addr).await;
+    let client = server.connect(proxy.addr).await;
+    let id = Uuid::new_v4();
+
+    client.insert(id, input(10)).await.unwrap();
+    let mut subscription = client.subscribe(id).await.unwrap();
+    wait_for(&mut subscription, 10).await;
+
+    proxy.cut();
+    timeout(TIMEOUT, async {
+        while client.list().await.is_err() {
+            sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("reconnect to admin server");
+
+    client.insert(id, input(20)).await.unwrap();
+    wait_for(&mut subscription, 20).await;
+}
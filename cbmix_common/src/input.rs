@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use tokio::sync::watch;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputState {
+    Waiting,
+    Live,
+    Lost,
+}
+
+#[derive(Clone, Debug)]
+pub struct InputStatus {
+    pub universe: u32,
+    pub state: InputState,
+    pub last_seen: Option<SystemTime>,
+}
+
+// status of each DMX input, keyed by the id of the node it drives
+pub type InputStatuses = HashMap<Uuid, InputStatus>;
+
+pub type Sender = watch::Sender<InputStatuses>;
+pub type Receiver = watch::Receiver<InputStatuses>;
+
+pub fn channel() -> (Sender, Receiver) {
+    watch::channel(HashMap::new())
+}
@@ -33,8 +33,10 @@ impl Sender {
     pub async fn shutdown(mut self) {
         let _ = self.notify.send(());
 
+        // wait for every receiver to be dropped, not just the first one to
+        // finish, so slower tasks still get to clean up
         drop(self.complete_tx);
-        let _ = self.complete_rx.recv().await;
+        while self.complete_rx.recv().await.is_some() {}
     }
 }
 
@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use crate::{Error, LossPolicy, OutputOptions};
+
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub enum Command {
+    AddInput {
+        universe: u32,
+        id: Uuid,
+        policy: LossPolicy,
+        timeout: Duration,
+        callback: oneshot::Sender<Result<(), Error>>,
+    },
+    RemoveInput {
+        universe: u32,
+        callback: oneshot::Sender<Result<(), Error>>,
+    },
+    AddOutput {
+        node: Uuid,
+        options: OutputOptions,
+        callback: oneshot::Sender<Result<Uuid, Error>>,
+    },
+    RemoveOutput {
+        id: Uuid,
+        callback: oneshot::Sender<Result<(), Error>>,
+    },
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use crate::command::Command;
+use crate::{Error, LossPolicy, OutputOptions};
+
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct DmxHandle {
+    dmx_tx: mpsc::Sender<Command>,
+}
+
+impl DmxHandle {
+    pub(crate) fn new(dmx_tx: mpsc::Sender<Command>) -> Self {
+        Self { dmx_tx }
+    }
+
+    // the input's node is created blank, unless it's already an input node
+    pub async fn add_input(
+        &self,
+        universe: u32,
+        id: Uuid,
+        policy: LossPolicy,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::AddInput {
+            universe,
+            id,
+            policy,
+            timeout,
+            callback: tx,
+        })
+        .await?;
+
+        rx.await.map_err(|_| Error::Closed)?
+    }
+
+    // the input's node is left in the graph with its last levels
+    pub async fn remove_input(&self, universe: u32) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::RemoveInput {
+            universe,
+            callback: tx,
+        })
+        .await?;
+
+        rx.await.map_err(|_| Error::Closed)?
+    }
+
+    // sends the channels of `node` out, returning an id for removing it
+    pub async fn add_output(&self, node: Uuid, options: OutputOptions) -> Result<Uuid, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::AddOutput {
+            node,
+            options,
+            callback: tx,
+        })
+        .await?;
+
+        rx.await.map_err(|_| Error::Closed)?
+    }
+
+    pub async fn remove_output(&self, id: Uuid) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::RemoveOutput { id, callback: tx })
+            .await?;
+
+        rx.await.map_err(|_| Error::Closed)?
+    }
+
+    async fn send(&self, command: Command) -> Result<(), Error> {
+        self.dmx_tx.send(command).await.map_err(|_| Error::Closed)
+    }
+}
@@ -0,0 +1,173 @@
+use std::mem::replace;
+use std::time::{Duration, SystemTime};
+
+use cbmix_common::input::{InputState, InputStatus};
+use ola::DmxBuffer;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+// how often a fading input is updated
+const FADE_STEP: Duration = Duration::from_millis(25);
+
+#[derive(Clone, Debug, Default)]
+pub enum LossPolicy {
+    // keep the last received frame
+    #[default]
+    Hold,
+    // fade the last received frame to zero
+    Fade(Duration),
+    // switch to the frames of another universe
+    Universe(u32),
+    // switch to the channels of a static node
+    Node(Uuid),
+}
+
+#[derive(Debug)]
+pub(crate) enum Action {
+    Insert(Box<DmxBuffer>),
+    CopyNode(Uuid),
+}
+
+#[derive(Debug)]
+pub(crate) struct Input {
+    pub id: Uuid,
+    pub universe: u32,
+    pub policy: LossPolicy,
+    timeout: Duration,
+    started: Instant,
+    last_seen: Option<(Instant, SystemTime)>,
+    channels: DmxBuffer,
+    backup: Option<DmxBuffer>,
+    state: State,
+}
+
+#[derive(Debug)]
+enum State {
+    Waiting,
+    Live,
+    Lost,
+    Fading { since: Instant, next: Instant },
+}
+
+impl Input {
+    pub fn new(id: Uuid, universe: u32, policy: LossPolicy, timeout: Duration) -> Self {
+        Self {
+            id,
+            universe,
+            policy,
+            timeout,
+            started: Instant::now(),
+            last_seen: None,
+            channels: DmxBuffer::new(),
+            backup: None,
+            state: State::Waiting,
+        }
+    }
+
+    pub fn status(&self) -> InputStatus {
+        InputStatus {
+            universe: self.universe,
+            state: match self.state {
+                State::Waiting => InputState::Waiting,
+                State::Live => InputState::Live,
+                State::Lost | State::Fading { .. } => InputState::Lost,
+            },
+            last_seen: self.last_seen.map(|(_, time)| time),
+        }
+    }
+
+    // record a frame from the input's own universe, returning true if the
+    // input just came back
+    pub fn receive(&mut self, channels: DmxBuffer) -> bool {
+        self.channels = channels;
+        self.last_seen = Some((Instant::now(), SystemTime::now()));
+
+        !matches!(replace(&mut self.state, State::Live), State::Live)
+    }
+
+    // record a frame from the backup universe, returning it if the input
+    // is currently failed over to it
+    pub fn receive_backup(&mut self, channels: DmxBuffer) -> Option<DmxBuffer> {
+        self.backup = Some(channels);
+        match self.state {
+            State::Lost => self.backup.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            State::Waiting | State::Live => Some(self.last_seen() + self.timeout),
+            State::Lost => None,
+            State::Fading { next, .. } => Some(next),
+        }
+    }
+
+    // check the input against the current time, returning what should
+    // happen to its node. returns true alongside the action if the input was
+    // just lost
+    pub fn tick(&mut self, now: Instant) -> (bool, Option<Action>) {
+        match self.state {
+            State::Waiting | State::Live if self.last_seen() + self.timeout <= now => {
+                let action = match &self.policy {
+                    LossPolicy::Hold => {
+                        self.state = State::Lost;
+                        None
+                    }
+                    LossPolicy::Fade(_) => {
+                        self.state = State::Fading {
+                            since: now,
+                            next: now,
+                        };
+                        self.fade(now)
+                    }
+                    LossPolicy::Universe(_) => {
+                        self.state = State::Lost;
+                        self.backup.clone().map(|b| Action::Insert(Box::new(b)))
+                    }
+                    LossPolicy::Node(id) => {
+                        self.state = State::Lost;
+                        Some(Action::CopyNode(*id))
+                    }
+                };
+
+                (true, action)
+            }
+            State::Fading { next, .. } if next <= now => (false, self.fade(now)),
+            _ => (false, None),
+        }
+    }
+
+    fn fade(&mut self, now: Instant) -> Option<Action> {
+        let (duration, since) = match (&self.policy, &self.state) {
+            (LossPolicy::Fade(duration), State::Fading { since, .. }) => (*duration, *since),
+            _ => return None,
+        };
+
+        let elapsed = now.duration_since(since);
+        let mut channels = self.channels.clone();
+        if elapsed >= duration {
+            self.state = State::Lost;
+            for c in channels.iter_mut() {
+                *c = 0;
+            }
+        } else {
+            let level = 1.0 - elapsed.as_secs_f32() / duration.as_secs_f32();
+            for c in channels.iter_mut() {
+                *c = (*c as f32 * level).round() as u8;
+            }
+            self.state = State::Fading {
+                since,
+                next: now + FADE_STEP,
+            };
+        }
+
+        Some(Action::Insert(Box::new(channels)))
+    }
+
+    fn last_seen(&self) -> Instant {
+        self.last_seen
+            .map(|(instant, _)| instant)
+            .unwrap_or(self.started)
+    }
+}
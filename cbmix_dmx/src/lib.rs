@@ -1,14 +1,37 @@
-use std::collections::HashMap;
+mod command;
+mod handle;
+mod input;
+mod output;
+mod sacn;
 
-use cbmix_common::shutdown;
+use std::collections::{HashMap, HashSet};
+use std::future::pending;
+use std::time::Duration;
+
+use command::Command;
+pub use handle::DmxHandle;
+pub use input::LossPolicy;
+use input::{Action, Input};
+use output::Output;
+pub use output::{Backend, OutputOptions, ShutdownLook};
+use sacn::SacnSender;
+pub use sacn::{SacnOptions, MAX_PRIORITY, MAX_UNIVERSE};
+
+use cbmix_common::{input as input_status, shutdown};
 use cbmix_graph::{GraphHandle, GraphUpdate, Node};
 use ola::{client::ClientAsync, connect_async, DmxBuffer};
 use thiserror::Error;
-use tokio::{net::TcpStream, sync::mpsc};
-use tracing::{error, trace, warn};
+use tokio::{
+    net::TcpStream,
+    sync::mpsc,
+    time::{sleep, sleep_until, Instant},
+};
+use tracing::{error, info, trace, warn};
 use uuid::Uuid;
 
 const OUTGOING_BUFFER_SIZE: usize = 15;
+const COMMAND_BUFFER_SIZE: usize = 15;
+const SHUTDOWN_FADE_STEP: Duration = Duration::from_millis(25);
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -20,6 +43,18 @@ pub enum Error {
     Subscribe,
     #[error("Failed to update graph with new input")]
     Insert,
+    #[error("Universe {0} is out of range for sACN")]
+    SacnUniverse(u32),
+    #[error("sACN priority {0} is above the maximum of {MAX_PRIORITY}")]
+    SacnPriority(u8),
+    #[error("Universe {0} already has an input")]
+    DuplicateInput(u32),
+    #[error("No input on universe {0}")]
+    UnknownInput(u32),
+    #[error("Output {0} does not exist")]
+    UnknownOutput(Uuid),
+    #[error("DMX connection is no longer running")]
+    Closed,
 }
 
 pub struct Dmx {
@@ -27,63 +62,242 @@ pub struct Dmx {
     graph: GraphHandle,
     subscription: mpsc::Sender<GraphUpdate>,
     graph_rx: mpsc::Receiver<GraphUpdate>,
-    inputs: HashMap<u32, Uuid>,
-    outputs: HashMap<Uuid, u32>,
+    commands_tx: mpsc::Sender<Command>,
+    commands_rx: mpsc::Receiver<Command>,
+    inputs: HashMap<u32, Input>,
+    backups: HashMap<u32, Vec<u32>>,
+    // OLA has no way to stop listening, so universes stay registered after
+    // their inputs are removed
+    registered: HashSet<u32>,
+    outputs: HashMap<Uuid, Output>,
+    sacn: SacnSender,
+    status: input_status::Sender,
     shutdown: shutdown::Receiver,
 }
 
 impl Dmx {
     pub async fn new(graph: GraphHandle, shutdown: shutdown::Receiver) -> Result<Self, Error> {
         let (subscription, graph_rx) = mpsc::channel(OUTGOING_BUFFER_SIZE);
+        let (commands_tx, commands_rx) = mpsc::channel(COMMAND_BUFFER_SIZE);
 
         let client = connect_async().await?;
+        let (status, _) = input_status::channel();
 
         Ok(Self {
             client,
             subscription,
             graph,
             graph_rx,
+            commands_tx,
+            commands_rx,
             outputs: HashMap::new(),
             inputs: HashMap::new(),
+            backups: HashMap::new(),
+            registered: HashSet::new(),
+            sacn: SacnSender::new(),
+            status,
             shutdown,
         })
     }
 
-    pub async fn add_output(&mut self, universe: u32, id: Uuid) -> Result<(), Error> {
-        let subscription = self
-            .graph
-            .subscribe(id, self.subscription.clone())
-            .await
-            .map_err(|_| Error::Subscribe)?;
-        self.outputs.insert(subscription, universe);
+    pub fn input_status(&self) -> input_status::Receiver {
+        self.status.subscribe()
+    }
+
+    // inputs and outputs are added through the handle once `serve` is running
+    pub fn handle(&self) -> DmxHandle {
+        DmxHandle::new(self.commands_tx.clone())
+    }
+
+    async fn add_output(&mut self, node: Uuid, options: OutputOptions) -> Result<Uuid, Error> {
+        for backend in &options.backends {
+            if let Backend::Sacn(sacn) = backend {
+                if let Some(universe) = options
+                    .universes
+                    .iter()
+                    .find(|u| !(1..=MAX_UNIVERSE).contains(*u))
+                {
+                    return Err(Error::SacnUniverse(*universe));
+                }
+                if sacn.priority > MAX_PRIORITY {
+                    return Err(Error::SacnPriority(sacn.priority));
+                }
+            }
+        }
+
+        // the graph sends the node's channels before it finishes subscribing,
+        // so keep taking updates meanwhile rather than letting them back up.
+        // they're handled once the new output is in place to receive its own
+        let (subscription, mut pending) = {
+            let subscribe = self.graph.subscribe(node, self.subscription.clone());
+            tokio::pin!(subscribe);
+
+            let mut pending = Vec::new();
+            loop {
+                tokio::select! {
+                    result = &mut subscribe => break (result, pending),
+                    Some(update) = self.graph_rx.recv() => pending.push(update),
+                }
+            }
+        };
+        while let Ok(update) = self.graph_rx.try_recv() {
+            pending.push(update);
+        }
 
-        // the main loop isn't running yet, so we need to handle the first
-        // event sent after subscribing to avoid building up backpressure
-        if let Some(update) = self.graph_rx.recv().await {
+        let subscription = subscription.map_err(|_| Error::Subscribe);
+        if let Ok(id) = subscription {
+            self.outputs.insert(id, Output::new(options));
+        }
+        for update in pending {
             self.handle_update(update).await;
         }
 
+        subscription
+    }
+
+    async fn remove_output(&mut self, id: Uuid) -> Result<(), Error> {
+        let output = self.outputs.remove(&id).ok_or(Error::UnknownOutput(id))?;
+        if let Err(e) = self.graph.unsubscribe(id).await {
+            warn!("failed to unsubscribe removed output {}: {}", id, e);
+        }
+
+        // the subscription is closed by now, so anything left for it is stale
+        while let Ok(update) = self.graph_rx.try_recv() {
+            match update {
+                GraphUpdate::Update { id: update_id, .. }
+                | GraphUpdate::Closed { id: update_id }
+                    if update_id == id => {}
+                update => self.handle_update(update).await,
+            }
+        }
+
+        // let sACN receivers know the source is gone, unless another output
+        // still sends the universe
+        for backend in &output.backends {
+            if let Backend::Sacn(options) = backend {
+                for universe in &output.universes {
+                    let still_sent = self.outputs.values().any(|other| {
+                        other.universes.contains(universe)
+                            && other.backends.iter().any(|b| matches!(b, Backend::Sacn(_)))
+                    });
+                    if still_sent {
+                        continue;
+                    }
+
+                    if let Err(e) = self
+                        .sacn
+                        .terminate(*universe, &output.channels, options)
+                        .await
+                    {
+                        error!("failed to terminate sacn universe {}: {}", universe, e);
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
-    pub async fn add_input(&mut self, universe: u32, id: Uuid) -> Result<(), Error> {
-        let node = Node::Input {
-            channels: DmxBuffer::new(),
-        };
+    async fn add_input(
+        &mut self,
+        universe: u32,
+        id: Uuid,
+        policy: LossPolicy,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        if self.inputs.contains_key(&universe) {
+            return Err(Error::DuplicateInput(universe));
+        }
+
+        // an input that's being replaced keeps its levels until new frames
+        // arrive
+        if !matches!(self.graph.get(id).await, Ok((Node::Input { .. }, _))) {
+            let node = Node::Input {
+                channels: DmxBuffer::new(),
+            };
+
+            self.graph
+                .insert(id, node)
+                .await
+                .map_err(|_| Error::Insert)?;
+        }
+
+        self.register(universe).await?;
+        if let LossPolicy::Universe(backup) = policy {
+            self.register(backup).await?;
+            self.backups.entry(backup).or_default().push(universe);
+        }
+
+        let input = Input::new(id, universe, policy, timeout);
+        self.status.send_modify(|status| {
+            status.insert(id, input.status());
+        });
+        self.inputs.insert(universe, input);
+
+        Ok(())
+    }
+
+    async fn remove_input(&mut self, universe: u32) -> Result<(), Error> {
+        let input = self
+            .inputs
+            .remove(&universe)
+            .ok_or(Error::UnknownInput(universe))?;
+
+        if let LossPolicy::Universe(backup) = input.policy {
+            if let Some(primaries) = self.backups.get_mut(&backup) {
+                primaries.retain(|primary| *primary != universe);
+                if primaries.is_empty() {
+                    self.backups.remove(&backup);
+                }
+            }
+        }
+        self.status.send_modify(|status| {
+            status.remove(&input.id);
+        });
 
-        self.graph
-            .insert(id, node)
-            .await
-            .map_err(|_| Error::Insert)?;
+        Ok(())
+    }
 
-        self.client.register_universe(universe).await?;
-        self.inputs.insert(universe, id);
+    async fn register(&mut self, universe: u32) -> Result<(), Error> {
+        if !self.registered.contains(&universe) {
+            self.client.register_universe(universe).await?;
+            self.registered.insert(universe);
+        }
 
         Ok(())
     }
 
+    async fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::AddInput {
+                universe,
+                id,
+                policy,
+                timeout,
+                callback,
+            } => {
+                _ = callback.send(self.add_input(universe, id, policy, timeout).await);
+            }
+            Command::RemoveInput { universe, callback } => {
+                _ = callback.send(self.remove_input(universe).await);
+            }
+            Command::AddOutput {
+                node,
+                options,
+                callback,
+            } => {
+                _ = callback.send(self.add_output(node, options).await);
+            }
+            Command::RemoveOutput { id, callback } => {
+                _ = callback.send(self.remove_output(id).await);
+            }
+        }
+    }
+
     pub async fn serve(mut self) {
         loop {
+            let deadline = self.next_deadline();
+
             tokio::select! {
                 update = self.graph_rx.recv() => match update {
                     Some(update) => self.handle_update(update).await,
@@ -92,27 +306,119 @@ impl Dmx {
                         break
                     },
                 },
+                Some(command) = self.commands_rx.recv() => self.handle_command(command).await,
                 update = self.client.recv() => match update {
                     Ok((universe, data)) => self.update_input(universe as u32, data).await,
                     Err(e) => {
                         error!("error occured while receiving from ola: {:?}", e);
                     },
                 },
+                _ = next_refresh(deadline) => {
+                    self.check_inputs().await;
+                    self.refresh_outputs().await;
+                }
                 _ = self.shutdown.recv() => break,
             };
         }
 
+        self.send_shutdown_looks().await;
+
         self.shutdown.force_shutdown().await
     }
 
+    async fn send_shutdown_looks(&mut self) {
+        let start = Instant::now();
+        loop {
+            let elapsed = start.elapsed();
+            let mut fading = false;
+            for output in self.outputs.values_mut() {
+                if let Some((frame, done)) = output.shutdown_frame(elapsed) {
+                    output.channels = frame;
+                    fading |= !done;
+                    send_output(&mut self.client, &mut self.sacn, output).await;
+                }
+            }
+
+            if !fading {
+                break;
+            }
+            sleep(SHUTDOWN_FADE_STEP).await;
+        }
+
+        for output in self.outputs.values() {
+            for backend in &output.backends {
+                if let Backend::Sacn(options) = backend {
+                    for universe in &output.universes {
+                        if let Err(e) = self
+                            .sacn
+                            .terminate(*universe, &output.channels, options)
+                            .await
+                        {
+                            error!("failed to terminate sacn universe {}: {}", universe, e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let inputs = self.inputs.values().filter_map(Input::deadline);
+        let outputs = self.outputs.values().filter_map(Output::deadline);
+
+        inputs.chain(outputs).min()
+    }
+
+    async fn check_inputs(&mut self) {
+        let now = Instant::now();
+        for input in self.inputs.values_mut() {
+            let (lost, action) = input.tick(now);
+            if lost {
+                warn!("lost dmx input on universe {}", input.universe);
+                let status = input.status();
+                self.status.send_modify(|statuses| {
+                    statuses.insert(input.id, status);
+                });
+            }
+
+            let channels = match action {
+                Some(Action::Insert(channels)) => *channels,
+                Some(Action::CopyNode(id)) => match self.graph.get(id).await {
+                    Ok((Node::Input { channels }, _)) => channels,
+                    Ok(_) => {
+                        warn!("failover node {} is not a static node, holding", id);
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("failed to get failover node {}: {}", id, e);
+                        continue;
+                    }
+                },
+                None => continue,
+            };
+
+            if let Err(e) = self.graph.insert(input.id, Node::Input { channels }).await {
+                error!("failed to send dmx input update to graph: {}", e);
+            }
+        }
+    }
+
+    async fn refresh_outputs(&mut self) {
+        let now = Instant::now();
+        for output in self.outputs.values_mut() {
+            if output.tick(now) {
+                send_output(&mut self.client, &mut self.sacn, output).await;
+            }
+        }
+    }
+
     async fn handle_update(&mut self, update: GraphUpdate) {
         match update {
-            GraphUpdate::Update { id, channels } => {
-                if let Some(universe) = self.outputs.get(&id) {
-                    if let Err(e) = self.client.send_dmx_streaming(*universe, &channels).await {
-                        error!("failed to update universe: {}", e);
+            GraphUpdate::Update { id, channels, .. } => {
+                if let Some(output) = self.outputs.get_mut(&id) {
+                    if output.update(channels) {
+                        send_output(&mut self.client, &mut self.sacn, output).await;
                     }
-                    trace!("sent buffer to ola: {:?}", channels);
                 } else {
                     warn!("recieved update from unknown output {}", id);
                 }
@@ -124,12 +430,81 @@ impl Dmx {
     }
 
     async fn update_input(&mut self, universe: u32, data: DmxBuffer) {
-        if let Some(id) = self.inputs.get(&universe) {
-            if let Err(e) = self.graph.insert(*id, Node::Input { channels: data }).await {
+        let mut updates = Vec::new();
+
+        if let Some(input) = self.inputs.get_mut(&universe) {
+            if input.receive(data.clone()) {
+                info!("dmx input on universe {} is live", universe);
+                let status = input.status();
+                self.status.send_modify(|statuses| {
+                    statuses.insert(input.id, status);
+                });
+            } else {
+                let last_seen = input.status().last_seen;
+                self.status.send_if_modified(|statuses| {
+                    if let Some(status) = statuses.get_mut(&input.id) {
+                        status.last_seen = last_seen;
+                    }
+                    false
+                });
+            }
+
+            updates.push((input.id, data.clone()));
+        }
+
+        if let Some(primaries) = self.backups.get(&universe) {
+            for primary in primaries {
+                if let Some(input) = self.inputs.get_mut(primary) {
+                    if let Some(channels) = input.receive_backup(data.clone()) {
+                        updates.push((input.id, channels));
+                    }
+                }
+            }
+        }
+
+        if updates.is_empty() {
+            if self.registered.contains(&universe) {
+                trace!(
+                    "ignoring dmx update for removed input on universe {}",
+                    universe
+                );
+            } else {
+                warn!("recieved dmx update for unknown univers {}", universe);
+            }
+        }
+
+        for (id, channels) in updates {
+            if let Err(e) = self.graph.insert(id, Node::Input { channels }).await {
                 error!("failed to send dmx input update to graph: {}", e);
             }
-        } else {
-            warn!("recieved dmx update for unknown univers {}", universe);
         }
     }
 }
+
+async fn send_output(client: &mut ClientAsync<TcpStream>, sacn: &mut SacnSender, output: &Output) {
+    for universe in &output.universes {
+        for backend in &output.backends {
+            match backend {
+                Backend::Ola => {
+                    if let Err(e) = client.send_dmx_streaming(*universe, &output.channels).await {
+                        error!("failed to update universe {}: {}", universe, e);
+                    }
+                    trace!("sent buffer to ola: {:?}", output.channels);
+                }
+                Backend::Sacn(options) => {
+                    if let Err(e) = sacn.send(*universe, &output.channels, options).await {
+                        error!("failed to send sacn universe {}: {}", universe, e);
+                    }
+                    trace!("sent buffer over sacn: {:?}", output.channels);
+                }
+            }
+        }
+    }
+}
+
+async fn next_refresh(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => pending().await,
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use crate::sacn::SacnOptions;
+
+use ola::DmxBuffer;
+use tokio::time::Instant;
+
+#[derive(Clone, Debug)]
+pub enum Backend {
+    Ola,
+    Sacn(SacnOptions),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ShutdownLook {
+    // leave the last frame latched on the receivers
+    #[default]
+    Hold,
+    Blackout,
+    Static(Box<DmxBuffer>),
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputOptions {
+    pub universes: Vec<u32>,
+    pub backends: Vec<Backend>,
+    pub refresh_period: Option<Duration>,
+    pub shutdown_look: ShutdownLook,
+    pub shutdown_fade: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Output {
+    pub universes: Vec<u32>,
+    pub backends: Vec<Backend>,
+    pub channels: DmxBuffer,
+    refresh: Option<Refresh>,
+    shutdown_look: ShutdownLook,
+    shutdown_fade: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Refresh {
+    period: Duration,
+    next: Instant,
+}
+
+impl Output {
+    pub fn new(options: OutputOptions) -> Self {
+        Self {
+            universes: options.universes,
+            backends: options.backends,
+            channels: DmxBuffer::new(),
+            refresh: options.refresh_period.map(|period| Refresh {
+                period,
+                next: Instant::now(),
+            }),
+            shutdown_look: options.shutdown_look,
+            shutdown_fade: options.shutdown_fade,
+        }
+    }
+
+    // the frame to send `elapsed` into shutdown, alongside whether the
+    // shutdown look has been reached. returns None if the output should hold
+    pub fn shutdown_frame(&self, elapsed: Duration) -> Option<(DmxBuffer, bool)> {
+        let target = match &self.shutdown_look {
+            ShutdownLook::Hold => return None,
+            ShutdownLook::Blackout => DmxBuffer::new(),
+            ShutdownLook::Static(channels) => (**channels).clone(),
+        };
+
+        match self.shutdown_fade {
+            Some(fade) if elapsed < fade => {
+                let level = elapsed.as_secs_f32() / fade.as_secs_f32();
+                let mut frame = DmxBuffer::new();
+                for (c, (from, to)) in frame
+                    .iter_mut()
+                    .zip(self.channels.iter().zip(target.iter()))
+                {
+                    *c = (*from as f32 + (*to as f32 - *from as f32) * level).round() as u8;
+                }
+
+                Some((frame, false))
+            }
+            _ => Some((target, true)),
+        }
+    }
+
+    // store a new frame, returning true if it should be sent right away.
+    // outputs with a refresh rate hold on to the frame until their next tick
+    pub fn update(&mut self, channels: DmxBuffer) -> bool {
+        self.channels = channels;
+        self.refresh.is_none()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.refresh.as_ref().map(|r| r.next)
+    }
+
+    // advance the refresh timer, returning true if the output is due
+    pub fn tick(&mut self, now: Instant) -> bool {
+        match &mut self.refresh {
+            Some(refresh) if refresh.next <= now => {
+                refresh.next += refresh.period;
+                if refresh.next <= now {
+                    // fell behind, don't try to catch up with a burst of frames
+                    refresh.next = now + refresh.period;
+                }
+
+                true
+            }
+            _ => false,
+        }
+    }
+}
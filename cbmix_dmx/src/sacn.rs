@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use ola::DmxBuffer;
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+
+pub const SACN_PORT: u16 = 5568;
+pub const DEFAULT_PRIORITY: u8 = 100;
+pub const MAX_PRIORITY: u8 = 200;
+pub const MAX_UNIVERSE: u32 = 63999;
+
+const PACKET_SIZE: usize = 638;
+const SOURCE_NAME_SIZE: usize = 64;
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+// the standard asks for three terminated packets in case some are lost
+const TERMINATE_REPEAT: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct SacnOptions {
+    // unicast destination, or the universe's multicast group if unset
+    pub destination: Option<IpAddr>,
+    pub priority: u8,
+    pub source_name: String,
+}
+
+impl Default for SacnOptions {
+    fn default() -> Self {
+        Self {
+            destination: None,
+            priority: DEFAULT_PRIORITY,
+            source_name: "cbmix".to_string(),
+        }
+    }
+}
+
+// a native E1.31 (streaming ACN) sender
+#[derive(Debug)]
+pub(crate) struct SacnSender {
+    cid: Uuid,
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+    sequences: HashMap<u32, u8>,
+}
+
+impl SacnSender {
+    pub fn new() -> Self {
+        Self {
+            cid: Uuid::new_v4(),
+            v4: None,
+            v6: None,
+            sequences: HashMap::new(),
+        }
+    }
+
+    pub async fn send(
+        &mut self,
+        universe: u32,
+        channels: &DmxBuffer,
+        options: &SacnOptions,
+    ) -> io::Result<()> {
+        self.send_packet(universe, channels, options, 0).await
+    }
+
+    // tell receivers the stream is ending so they don't wait out the
+    // source timeout
+    pub async fn terminate(
+        &mut self,
+        universe: u32,
+        channels: &DmxBuffer,
+        options: &SacnOptions,
+    ) -> io::Result<()> {
+        for _ in 0..TERMINATE_REPEAT {
+            self.send_packet(universe, channels, options, OPTION_STREAM_TERMINATED)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn send_packet(
+        &mut self,
+        universe: u32,
+        channels: &DmxBuffer,
+        options: &SacnOptions,
+        flags: u8,
+    ) -> io::Result<()> {
+        let sequence = self.sequences.entry(universe).or_default();
+        let packet = data_packet(
+            &self.cid,
+            universe as u16,
+            *sequence,
+            channels,
+            options,
+            flags,
+        );
+        *sequence = sequence.wrapping_add(1);
+
+        let destination = match options.destination {
+            Some(ip) => SocketAddr::new(ip, SACN_PORT),
+            None => SocketAddr::new(multicast_group(universe as u16).into(), SACN_PORT),
+        };
+
+        self.socket(&destination)
+            .await?
+            .send_to(&packet, destination)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn socket(&mut self, destination: &SocketAddr) -> io::Result<&UdpSocket> {
+        let (socket, bind) = match destination {
+            SocketAddr::V4(_) => (&mut self.v4, IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            SocketAddr::V6(_) => (&mut self.v6, IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        };
+
+        if socket.is_none() {
+            *socket = Some(UdpSocket::bind(SocketAddr::new(bind, 0)).await?);
+        }
+
+        Ok(socket.as_ref().expect("get bound sacn socket"))
+    }
+}
+
+fn multicast_group(universe: u16) -> Ipv4Addr {
+    let [hi, lo] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, hi, lo)
+}
+
+fn flags_and_length(length: usize) -> [u8; 2] {
+    (0x7000 | length as u16).to_be_bytes()
+}
+
+fn data_packet(
+    cid: &Uuid,
+    universe: u16,
+    sequence: u8,
+    channels: &DmxBuffer,
+    options: &SacnOptions,
+    flags: u8,
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(PACKET_SIZE);
+
+    // root layer
+    packet.extend_from_slice(&0x0010u16.to_be_bytes());
+    packet.extend_from_slice(&0x0000u16.to_be_bytes());
+    packet.extend_from_slice(ACN_PACKET_IDENTIFIER);
+    packet.extend_from_slice(&flags_and_length(PACKET_SIZE - packet.len()));
+    packet.extend_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+    packet.extend_from_slice(cid.as_bytes());
+
+    // framing layer
+    packet.extend_from_slice(&flags_and_length(PACKET_SIZE - packet.len()));
+    packet.extend_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+    let mut source_name = [0; SOURCE_NAME_SIZE];
+    let name = &options.source_name;
+    let mut name_len = name.len().min(SOURCE_NAME_SIZE - 1);
+    while !name.is_char_boundary(name_len) {
+        name_len -= 1;
+    }
+    source_name[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+    packet.extend_from_slice(&source_name);
+    packet.push(options.priority);
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.push(sequence);
+    packet.push(flags);
+    packet.extend_from_slice(&universe.to_be_bytes());
+
+    // dmp layer
+    packet.extend_from_slice(&flags_and_length(PACKET_SIZE - packet.len()));
+    packet.push(VECTOR_DMP_SET_PROPERTY);
+    packet.push(0xa1);
+    packet.extend_from_slice(&0x0000u16.to_be_bytes());
+    packet.extend_from_slice(&0x0001u16.to_be_bytes());
+    packet.extend_from_slice(&513u16.to_be_bytes());
+    packet.push(0);
+    packet.extend(channels.iter());
+
+    packet
+}
@@ -1,5 +1,6 @@
-use crate::{Error, GraphUpdate, Node};
+use crate::{ChannelLevel, Error, GraphUpdate, Node};
 
+use ola::DmxBuffer;
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
@@ -8,18 +9,47 @@ pub enum Command {
     Insert {
         id: Uuid,
         node: Node,
+        revision: Option<u64>,
+        journal: bool,
+        callback: oneshot::Sender<Result<u64, Error>>,
+    },
+    InsertBatch {
+        nodes: Vec<(Uuid, Node)>,
+        held: Vec<(Uuid, Node)>,
+        prune: bool,
+        journal: bool,
+        callback: oneshot::Sender<Result<(), Error>>,
+    },
+    SetChannels {
+        id: Uuid,
+        levels: Vec<ChannelLevel>,
         callback: oneshot::Sender<Result<(), Error>>,
     },
     Remove {
         id: Uuid,
+        revision: Option<u64>,
+        journal: bool,
+        callback: oneshot::Sender<Result<(), Error>>,
+    },
+    Undo {
+        callback: oneshot::Sender<Result<(), Error>>,
+    },
+    Redo {
         callback: oneshot::Sender<Result<(), Error>>,
     },
     Get {
         id: Uuid,
-        callback: oneshot::Sender<Result<Node, Error>>,
+        callback: oneshot::Sender<Result<(Node, u64), Error>>,
     },
     List {
-        callback: oneshot::Sender<Vec<(Uuid, Node)>>,
+        callback: oneshot::Sender<Vec<(Uuid, Node, u64)>>,
+    },
+    GetState {
+        id: Uuid,
+        callback: oneshot::Sender<Result<DmxBuffer, Error>>,
+    },
+    GetStates {
+        callback: oneshot::Sender<Vec<(Uuid, DmxBuffer)>>,
     },
     Subscribe {
         id: Uuid,
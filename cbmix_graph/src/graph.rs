@@ -3,7 +3,7 @@ use std::collections::{
     HashMap, VecDeque,
 };
 
-use crate::node::{self, Node};
+use crate::node::{self, ChannelLevel, Node};
 use crate::subscription::{self, GraphUpdate, Subscription};
 use crate::transaction::{MapLike, Transaction};
 
@@ -16,8 +16,8 @@ use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Input {0} does not exist")]
-    MissingInput(u32),
+    #[error("Input {index} ({id}) does not exist")]
+    MissingInput { index: u32, id: Uuid },
     #[error("A node does not exist with the given id")]
     UnknownNode,
     #[error("A subscription does not exist with the given id")]
@@ -26,6 +26,12 @@ pub enum Error {
     Subscribe(#[from] subscription::Error),
     #[error("Operation would create a dependency cycle")]
     Cycle,
+    #[error("Only input nodes have channels to set")]
+    NotInput,
+    #[error("{count} channels from channel {channel} run past the end of the universe")]
+    Channel { channel: u32, count: u32 },
+    #[error("Node is at revision {revision}, not {expected}")]
+    Conflict { expected: u64, revision: u64 },
 }
 
 #[derive(Clone, Debug, Default)]
@@ -34,6 +40,8 @@ pub struct SceneGraph {
     nodes: HashMap<Uuid, Node>,
     node_states: HashMap<Uuid, DmxBuffer>,
     dependencies: HashMap<Uuid, Dependencies>,
+    revisions: HashMap<Uuid, u64>,
+    revision: u64,
 }
 
 impl SceneGraph {
@@ -41,7 +49,18 @@ impl SceneGraph {
         Default::default()
     }
 
-    pub async fn insert(&mut self, id: Uuid, node: Node) -> Result<(), Error> {
+    // fails if `expected` is given and the node has changed since then
+    pub async fn insert(
+        &mut self,
+        id: Uuid,
+        node: Node,
+        expected: Option<u64>,
+    ) -> Result<u64, Error> {
+        self.check_revision(&id, expected)?;
+        // writing a node back unchanged, like a DMX input repeating its last
+        // frame, keeps its revision
+        let changed = self.nodes.get(&id) != Some(&node);
+
         let mut dependencies = Transaction::new(&mut self.dependencies);
         let reverse = match dependencies.get_mut(&id) {
             Some(node) => {
@@ -66,7 +85,10 @@ impl SceneGraph {
                         "missing input {} of {} found while inserting {}",
                         index, dependency_id, id
                     );
-                    return Err(Error::MissingInput(index as u32));
+                    return Err(Error::MissingInput {
+                        index: index as u32,
+                        id: *dependency_id,
+                    });
                 }
             } else {
                 forward.push(None);
@@ -77,30 +99,82 @@ impl SceneGraph {
         nodes.insert(id, node);
         dependencies.insert(id, Dependencies { forward, reverse });
 
-        Self::update(
+        let previous = self.revisions.get(&id).copied();
+        if changed {
+            self.revision += 1;
+            self.revisions.insert(id, self.revision);
+        }
+
+        let result = Self::update(
             &id,
             nodes,
             Transaction::new(&mut self.node_states),
             dependencies,
+            &self.revisions,
             &mut self.subscriptions,
         )
-        .await
+        .await;
+
+        match result {
+            Ok(()) => Ok(self.revision(&id)),
+            Err(e) => {
+                match previous {
+                    Some(revision) => self.revisions.insert(id, revision),
+                    None => self.revisions.remove(&id),
+                };
+                Err(e)
+            }
+        }
+    }
+
+    // applied in one step, so clients setting different channels of the same
+    // node don't overwrite each other
+    pub async fn set_channels(&mut self, id: Uuid, levels: &[ChannelLevel]) -> Result<(), Error> {
+        let Node::Input { channels } = self.get(&id)? else {
+            return Err(Error::NotInput);
+        };
+
+        let mut channels: Vec<u8> = channels.clone().into();
+        for level in levels {
+            let end = level.channel.saturating_add(level.count);
+            if end as usize > channels.len() {
+                return Err(Error::Channel {
+                    channel: level.channel,
+                    count: level.count,
+                });
+            }
+
+            channels[level.channel as usize..end as usize].fill(level.value);
+        }
+
+        let channels = channels.try_into().expect("keep universe size");
+        self.insert(id, Node::Input { channels }, None).await?;
+
+        Ok(())
     }
 
-    pub async fn remove(&mut self, id: Uuid) -> Result<(), Error> {
+    // returns the nodes that read from the removed one, as they were before
+    // being unlinked from it
+    pub async fn remove(
+        &mut self,
+        id: Uuid,
+        expected: Option<u64>,
+    ) -> Result<Vec<(Uuid, Node)>, Error> {
+        self.check_revision(&id, expected)?;
+
         if let Entry::Occupied(occupied) = self.nodes.entry(id) {
             occupied.remove();
+            self.revisions.remove(&id);
 
             if let Some(dependencies) = self.dependencies.get(&id) {
                 let Dependencies { forward, reverse } = dependencies.clone();
 
                 Self::disconnect_forward(&mut self.dependencies, &id, &forward);
-                self.disconnect_reverse(&id, &reverse).await;
+                Ok(self.disconnect_reverse(&id, &reverse).await)
             } else {
                 warn!("dependency not found for subscription {}", id);
-            };
-
-            Ok(())
+                Ok(Vec::new())
+            }
         } else {
             Err(Error::UnknownNode)
         }
@@ -118,6 +192,36 @@ impl SceneGraph {
         self.nodes.iter()
     }
 
+    // revisions count up across the whole graph, so a node that's removed and
+    // inserted again never repeats one. a missing node is at revision 0
+    pub fn revision(&self, id: &Uuid) -> u64 {
+        self.revisions.get(id).copied().unwrap_or_default()
+    }
+
+    fn check_revision(&self, id: &Uuid, expected: Option<u64>) -> Result<(), Error> {
+        match expected {
+            Some(expected) if expected != self.revision(id) => Err(Error::Conflict {
+                expected,
+                revision: self.revision(id),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // the last computed output of a node
+    pub fn state(&self, id: &Uuid) -> Result<&DmxBuffer, Error> {
+        self.nodes
+            .get(id)
+            .and(self.node_states.get(id))
+            .ok_or(Error::UnknownNode)
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = (&Uuid, &DmxBuffer)> {
+        self.nodes
+            .keys()
+            .filter_map(|id| self.node_states.get(id).map(|state| (id, state)))
+    }
+
     fn disconnect_forward<D>(dependencies: &mut D, id: &Uuid, forward: &[Option<(Uuid, Index)>])
     where
         D: MapLike<Uuid, Dependencies>,
@@ -134,21 +238,32 @@ impl SceneGraph {
         }
     }
 
-    async fn disconnect_reverse(&mut self, id: &Uuid, reverse: &Arena<Dependent>) {
+    async fn disconnect_reverse(
+        &mut self,
+        id: &Uuid,
+        reverse: &Arena<Dependent>,
+    ) -> Vec<(Uuid, Node)> {
+        let mut unlinked: Vec<(Uuid, Node)> = Vec::new();
         for (_, dependent) in reverse {
             match dependent {
                 Dependent::Node { id: node_id, index } => match self.nodes.get_mut(node_id) {
                     Some(node) => {
+                        if !unlinked.iter().any(|(i, _)| i == node_id) {
+                            unlinked.push((*node_id, node.clone()));
+                        }
                         node.unlink(*index);
                         self.dependencies
                             .get_mut(node_id)
                             .expect("get dependencies of updated node")
                             .forward[*index as usize] = None;
+                        self.revision += 1;
+                        self.revisions.insert(*node_id, self.revision);
                         if let Err(e) = Self::update(
                             node_id,
                             Transaction::new(&mut self.nodes),
                             Transaction::new(&mut self.node_states),
                             Transaction::new(&mut self.dependencies),
+                            &self.revisions,
                             &mut self.subscriptions,
                         )
                         .await
@@ -172,6 +287,8 @@ impl SceneGraph {
                 },
             }
         }
+
+        unlinked
     }
 
     async fn update<'a>(
@@ -179,6 +296,7 @@ impl SceneGraph {
         mut nodes: Transaction<'a, Uuid, Node>,
         mut node_states: Transaction<'a, Uuid, DmxBuffer>,
         mut dependencies: Transaction<'a, Uuid, Dependencies>,
+        revisions: &HashMap<Uuid, u64>,
         subscriptions: &mut HashMap<Uuid, Subscription>,
     ) -> Result<(), Error> {
         let mut updates = Vec::new();
@@ -235,7 +353,7 @@ impl SceneGraph {
 
         for update in updates {
             if let Some(subscription) = subscriptions.get_mut(&update) {
-                if subscription.update(&node_states).await.is_err() {
+                if subscription.update(&node_states, revisions).await.is_err() {
                     warn!("removing stale subscription {}", update);
                     if let Some(input_dependencies) = dependencies.get_mut(&subscription.input) {
                         input_dependencies.reverse.remove(subscription.index);
@@ -268,7 +386,15 @@ impl SceneGraph {
                 .insert(Dependent::Subscription { id });
             self.subscriptions.insert(
                 id,
-                Subscription::new(id, input, index, &self.node_states, channel).await?,
+                Subscription::new(
+                    id,
+                    input,
+                    index,
+                    &self.node_states,
+                    &self.revisions,
+                    channel,
+                )
+                .await?,
             );
             trace!("created new subscription {}", id);
 
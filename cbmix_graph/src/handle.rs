@@ -1,6 +1,7 @@
 use crate::command::Command;
-use crate::{Error, GraphUpdate, Node};
+use crate::{ChannelLevel, Error, GraphUpdate, Node};
 
+use ola::DmxBuffer;
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
@@ -14,12 +15,94 @@ impl GraphHandle {
         Self { graph_tx }
     }
 
+    // for changes driven by inputs and playback, which stay out of the undo
+    // history
     pub async fn insert(&self, id: Uuid, node: Node) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
         self.graph_tx
             .send(Command::Insert {
                 id,
                 node,
+                revision: None,
+                journal: false,
+                callback: tx,
+            })
+            .await?;
+        rx.await??;
+
+        Ok(())
+    }
+
+    // for edits people make, which can be undone. fails with a conflict
+    // unless the node is still at `revision`, returning the node's new
+    // revision
+    pub async fn insert_checked(
+        &self,
+        id: Uuid,
+        node: Node,
+        revision: Option<u64>,
+    ) -> Result<u64, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.graph_tx
+            .send(Command::Insert {
+                id,
+                node,
+                revision,
+                journal: true,
+                callback: tx,
+            })
+            .await?;
+
+        rx.await?
+    }
+
+    // nodes are inserted after any in the batch they read from. like
+    // `insert`, this stays out of the undo history
+    pub async fn insert_batch(&self, nodes: Vec<(Uuid, Node)>) -> Result<(), Error> {
+        self.send_insert_batch(nodes, Vec::new(), false, false)
+            .await
+    }
+
+    // like `insert_batch`, but undone as one edit. with `prune`, nodes outside
+    // the batch are removed. nodes in `held` are inserted in place of their
+    // batch definition, which is still what the edit records, for callers that
+    // fade to the batch themselves
+    pub async fn insert_batch_checked(
+        &self,
+        nodes: Vec<(Uuid, Node)>,
+        held: Vec<(Uuid, Node)>,
+        prune: bool,
+    ) -> Result<(), Error> {
+        self.send_insert_batch(nodes, held, prune, true).await
+    }
+
+    async fn send_insert_batch(
+        &self,
+        nodes: Vec<(Uuid, Node)>,
+        held: Vec<(Uuid, Node)>,
+        prune: bool,
+        journal: bool,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.graph_tx
+            .send(Command::InsertBatch {
+                nodes,
+                held,
+                prune,
+                journal,
+                callback: tx,
+            })
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_channels(&self, id: Uuid, levels: Vec<ChannelLevel>) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.graph_tx
+            .send(Command::SetChannels {
+                id,
+                levels,
                 callback: tx,
             })
             .await?;
@@ -28,15 +111,47 @@ impl GraphHandle {
     }
 
     pub async fn remove(&self, id: Uuid) -> Result<(), Error> {
+        self.send_remove(id, None, false).await
+    }
+
+    pub async fn remove_checked(&self, id: Uuid, revision: Option<u64>) -> Result<(), Error> {
+        self.send_remove(id, revision, true).await
+    }
+
+    async fn send_remove(
+        &self,
+        id: Uuid,
+        revision: Option<u64>,
+        journal: bool,
+    ) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
         self.graph_tx
-            .send(Command::Remove { id, callback: tx })
+            .send(Command::Remove {
+                id,
+                revision,
+                journal,
+                callback: tx,
+            })
             .await?;
 
         rx.await?
     }
 
-    pub async fn get(&self, id: Uuid) -> Result<Node, Error> {
+    pub async fn undo(&self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.graph_tx.send(Command::Undo { callback: tx }).await?;
+
+        rx.await?
+    }
+
+    pub async fn redo(&self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.graph_tx.send(Command::Redo { callback: tx }).await?;
+
+        rx.await?
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<(Node, u64), Error> {
         let (tx, rx) = oneshot::channel();
         self.graph_tx
             .send(Command::Get { id, callback: tx })
@@ -45,13 +160,31 @@ impl GraphHandle {
         rx.await?
     }
 
-    pub async fn list(&self) -> Result<Vec<(Uuid, Node)>, Error> {
+    pub async fn list(&self) -> Result<Vec<(Uuid, Node, u64)>, Error> {
         let (tx, rx) = oneshot::channel();
         self.graph_tx.send(Command::List { callback: tx }).await?;
 
         Ok(rx.await?)
     }
 
+    pub async fn get_state(&self, id: Uuid) -> Result<DmxBuffer, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.graph_tx
+            .send(Command::GetState { id, callback: tx })
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_states(&self) -> Result<Vec<(Uuid, DmxBuffer)>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.graph_tx
+            .send(Command::GetStates { callback: tx })
+            .await?;
+
+        Ok(rx.await?)
+    }
+
     pub async fn subscribe(
         &self,
         id: Uuid,
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+use crate::Node;
+
+use uuid::Uuid;
+
+// how many edits can be undone
+const DEPTH: usize = 100;
+
+// a node's definition on either side of an edit, None where it didn't exist
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub id: Uuid,
+    pub before: Option<Node>,
+    pub after: Option<Node>,
+}
+
+// the changes made by one request, in the order they were made. a removal
+// lists the dependents it unlinked before the node itself, so undoing in
+// reverse brings the node back before relinking them
+pub type Edit = Vec<Change>;
+
+#[derive(Debug, Default)]
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    // the node whose channels were set by the newest undo entry, while
+    // nothing else has been recorded since
+    channels: Option<Uuid>,
+}
+
+impl History {
+    pub fn record(&mut self, mut edit: Edit) {
+        edit.retain(|change| change.before != change.after);
+        if edit.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        self.push_undo(edit);
+    }
+
+    // folds into the newest entry if that also only set this node's channels,
+    // keeping its `before` so undo goes back to where the run started
+    pub fn record_channels(&mut self, change: Change) {
+        let id = change.id;
+        if self.channels == Some(id) {
+            if let Some(last) = self.undo.back_mut() {
+                last[0].after = change.after;
+                if last[0].before == last[0].after {
+                    self.undo.pop_back();
+                    self.channels = None;
+                }
+                return;
+            }
+        }
+
+        if change.before != change.after {
+            self.record(vec![change]);
+            self.channels = Some(id);
+        }
+    }
+
+    pub fn take_undo(&mut self) -> Option<Edit> {
+        self.channels = None;
+        self.undo.pop_back()
+    }
+
+    pub fn take_redo(&mut self) -> Option<Edit> {
+        self.channels = None;
+        self.redo.pop()
+    }
+
+    pub fn undone(&mut self, edit: Edit) {
+        self.redo.push(edit);
+    }
+
+    pub fn redone(&mut self, edit: Edit) {
+        self.push_undo(edit);
+    }
+
+    fn push_undo(&mut self, edit: Edit) {
+        self.channels = None;
+        if self.undo.len() == DEPTH {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(edit);
+    }
+}
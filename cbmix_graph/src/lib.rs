@@ -1,17 +1,23 @@
 mod command;
 mod graph;
 mod handle;
+mod history;
 mod node;
 mod subscription;
 mod transaction;
 
 use command::Command;
+pub use graph::Error as SceneError;
 use graph::SceneGraph;
 pub use handle::GraphHandle;
-pub use node::Node;
+use history::{Change, Edit, History};
+pub use node::{ChannelLevel, Node};
 pub use subscription::GraphUpdate;
 
+use std::collections::{HashMap, HashSet};
+
 use cbmix_common::shutdown;
+use ola::DmxBuffer;
 use thiserror::Error;
 use tokio::sync::{mpsc, oneshot};
 use tracing::{error, trace};
@@ -29,9 +35,15 @@ pub enum Error {
     #[error("Subscription does not exist")]
     MissingSubscription,
     #[error("Unable to insert: {0}")]
-    Insert(graph::Error),
+    Insert(SceneError),
+    #[error("Unable to remove: {0}")]
+    Remove(SceneError),
+    #[error("No edits to undo or redo")]
+    EmptyHistory,
+    #[error("Unable to replay edit: {0}")]
+    Replay(SceneError),
     #[error("Unable to subscribe: {0}")]
-    Subscribe(graph::Error),
+    Subscribe(SceneError),
     #[error("Unable to send command to graph manager")]
     Send(#[from] mpsc::error::SendError<Command>),
     #[error("Graph manager suddenly stopped responding")]
@@ -40,6 +52,7 @@ pub enum Error {
 
 pub struct Graph {
     graph: SceneGraph,
+    history: History,
     incoming_tx: mpsc::Sender<Command>,
     incoming_rx: mpsc::Receiver<Command>,
     shutdown: shutdown::Receiver,
@@ -51,6 +64,7 @@ impl Graph {
 
         Self {
             graph: SceneGraph::new(),
+            history: History::default(),
             incoming_tx,
             incoming_rx,
             shutdown,
@@ -72,19 +86,72 @@ impl Graph {
             };
 
             match event {
-                Command::Insert { id, node, callback } => {
+                Command::Insert {
+                    id,
+                    node,
+                    revision,
+                    journal,
+                    callback,
+                } => {
                     trace!("inserting node {}: {:?}", id, node);
-                    _ = callback.send(self.graph.insert(id, node).await.map_err(Error::Insert));
+                    _ = callback.send(
+                        self.insert(id, node, revision, journal)
+                            .await
+                            .map_err(Error::Insert),
+                    );
+                }
+                Command::InsertBatch {
+                    nodes,
+                    held,
+                    prune,
+                    journal,
+                    callback,
+                } => {
+                    trace!("inserting {} nodes", nodes.len());
+                    _ = callback.send(
+                        self.insert_batch(nodes, held, prune, journal)
+                            .await
+                            .map_err(Error::Insert),
+                    );
+                }
+                Command::SetChannels {
+                    id,
+                    levels,
+                    callback,
+                } => {
+                    trace!("setting channels of {}: {:?}", id, levels);
+                    _ = callback.send(self.set_channels(id, &levels).await.map_err(|e| match e {
+                        SceneError::UnknownNode => Error::MissingNode,
+                        e => Error::Insert(e),
+                    }));
                 }
-                Command::Remove { id, callback } => {
+                Command::Remove {
+                    id,
+                    revision,
+                    journal,
+                    callback,
+                } => {
                     trace!("removing node {}", id);
-                    _ = callback.send(self.graph.remove(id).await.map_err(|_| Error::MissingNode));
+                    _ = callback.send(self.remove(id, revision, journal).await.map_err(
+                        |e| match e {
+                            SceneError::UnknownNode => Error::MissingNode,
+                            e => Error::Remove(e),
+                        },
+                    ));
+                }
+                Command::Undo { callback } => {
+                    trace!("undoing last edit");
+                    _ = callback.send(self.undo().await);
+                }
+                Command::Redo { callback } => {
+                    trace!("redoing last undone edit");
+                    _ = callback.send(self.redo().await);
                 }
                 Command::Get { id, callback } => {
                     _ = callback.send(
                         self.graph
                             .get(&id)
-                            .map(|n| n.clone())
+                            .map(|n| (n.clone(), self.graph.revision(&id)))
                             .map_err(|_| Error::MissingNode),
                     );
                 }
@@ -92,8 +159,24 @@ impl Graph {
                     _ = callback.send(
                         self.graph
                             .iter()
-                            .map(|(i, n)| (*i, n.clone()))
-                            .collect::<Vec<(Uuid, Node)>>(),
+                            .map(|(i, n)| (*i, n.clone(), self.graph.revision(i)))
+                            .collect::<Vec<(Uuid, Node, u64)>>(),
+                    );
+                }
+                Command::GetState { id, callback } => {
+                    _ = callback.send(
+                        self.graph
+                            .state(&id)
+                            .cloned()
+                            .map_err(|_| Error::MissingNode),
+                    );
+                }
+                Command::GetStates { callback } => {
+                    _ = callback.send(
+                        self.graph
+                            .states()
+                            .map(|(i, s)| (*i, s.clone()))
+                            .collect::<Vec<(Uuid, DmxBuffer)>>(),
                     );
                 }
                 Command::Subscribe {
@@ -122,4 +205,338 @@ impl Graph {
 
         self.shutdown.force_shutdown().await
     }
+
+    async fn insert(
+        &mut self,
+        id: Uuid,
+        node: Node,
+        revision: Option<u64>,
+        journal: bool,
+    ) -> Result<u64, SceneError> {
+        let before = self.graph.get(&id).ok().cloned();
+        let revision = self.graph.insert(id, node, revision).await?;
+        if journal {
+            self.history.record(vec![self.change(id, before)]);
+        }
+
+        Ok(revision)
+    }
+
+    // stops at the first node that fails, leaving the ones before it inserted.
+    // what was applied is still journaled, so it can be undone
+    async fn insert_batch(
+        &mut self,
+        nodes: Vec<(Uuid, Node)>,
+        held: Vec<(Uuid, Node)>,
+        prune: bool,
+        journal: bool,
+    ) -> Result<(), SceneError> {
+        let mut edit = Edit::new();
+        let result = self.apply_batch(nodes, held, prune, &mut edit).await;
+        if journal {
+            self.history.record(edit);
+        }
+
+        result
+    }
+
+    async fn apply_batch(
+        &mut self,
+        mut nodes: Vec<(Uuid, Node)>,
+        held: Vec<(Uuid, Node)>,
+        prune: bool,
+        edit: &mut Edit,
+    ) -> Result<(), SceneError> {
+        let mut held: HashMap<Uuid, Node> = held.into_iter().collect();
+        let batch: HashSet<Uuid> = nodes.iter().map(|(id, _)| *id).collect();
+        let pruned: Vec<Uuid> = match prune {
+            true => self
+                .graph
+                .iter()
+                .map(|(id, _)| *id)
+                .filter(|id| !batch.contains(id))
+                .collect(),
+            false => Vec::new(),
+        };
+
+        while !nodes.is_empty() {
+            let pending: HashSet<Uuid> = nodes.iter().map(|(id, _)| *id).collect();
+            let (ready, blocked): (Vec<_>, Vec<_>) = nodes.into_iter().partition(|(_, node)| {
+                node.dependencies()
+                    .iter()
+                    .flatten()
+                    .all(|dependency| !pending.contains(dependency))
+            });
+            if ready.is_empty() {
+                return Err(SceneError::Cycle);
+            }
+
+            for (id, node) in ready {
+                let before = self.graph.get(&id).ok().cloned();
+                let applied = held.remove(&id).unwrap_or_else(|| node.clone());
+                self.graph.insert(id, applied, None).await?;
+                edit.push(Change {
+                    id,
+                    before,
+                    after: Some(node),
+                });
+            }
+            nodes = blocked;
+        }
+
+        for id in pruned {
+            let before = self.graph.get(&id).ok().cloned();
+            let unlinked = self.graph.remove(id, None).await?;
+            edit.extend(self.removal(id, before, unlinked));
+        }
+
+        Ok(())
+    }
+
+    // only edits from people set channels, so these are always journaled. a
+    // run of them on one node, like a fader being dragged, is a single edit
+    async fn set_channels(&mut self, id: Uuid, levels: &[ChannelLevel]) -> Result<(), SceneError> {
+        let before = self.graph.get(&id).ok().cloned();
+        self.graph.set_channels(id, levels).await?;
+        self.history.record_channels(self.change(id, before));
+
+        Ok(())
+    }
+
+    async fn remove(
+        &mut self,
+        id: Uuid,
+        revision: Option<u64>,
+        journal: bool,
+    ) -> Result<(), SceneError> {
+        let before = self.graph.get(&id).ok().cloned();
+        let unlinked = self.graph.remove(id, revision).await?;
+        if journal {
+            self.history.record(self.removal(id, before, unlinked));
+        }
+
+        Ok(())
+    }
+
+    // an edit that fails to replay is dropped from the history, since part of
+    // it may already be applied
+    async fn undo(&mut self) -> Result<(), Error> {
+        let edit = self.history.take_undo().ok_or(Error::EmptyHistory)?;
+        for change in edit.iter().rev() {
+            self.restore(change.id, change.before.clone())
+                .await
+                .map_err(Error::Replay)?;
+        }
+        self.history.undone(edit);
+
+        Ok(())
+    }
+
+    async fn redo(&mut self) -> Result<(), Error> {
+        let edit = self.history.take_redo().ok_or(Error::EmptyHistory)?;
+        for change in edit.iter() {
+            self.restore(change.id, change.after.clone())
+                .await
+                .map_err(Error::Replay)?;
+        }
+        self.history.redone(edit);
+
+        Ok(())
+    }
+
+    async fn restore(&mut self, id: Uuid, node: Option<Node>) -> Result<(), SceneError> {
+        match node {
+            Some(node) => self.graph.insert(id, node, None).await.map(|_| ()),
+            None => match self.graph.remove(id, None).await {
+                Ok(_) | Err(SceneError::UnknownNode) => Ok(()),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn removal(&self, id: Uuid, before: Option<Node>, unlinked: Vec<(Uuid, Node)>) -> Edit {
+        let mut edit: Edit = unlinked
+            .into_iter()
+            .map(|(i, node)| self.change(i, Some(node)))
+            .collect();
+        edit.push(Change {
+            id,
+            before,
+            after: None,
+        });
+
+        edit
+    }
+
+    fn change(&self, id: Uuid, before: Option<Node>) -> Change {
+        Change {
+            id,
+            before,
+            after: self.graph.get(&id).ok().cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the shutdown sender has to outlive the graph
+    fn graph() -> (GraphHandle, shutdown::Sender) {
+        let shutdown = shutdown::Sender::new();
+        let graph = Graph::new(shutdown.subscribe());
+        let handle = graph.handle();
+        tokio::spawn(graph.serve());
+
+        (handle, shutdown)
+    }
+
+    fn input(levels: &[u8]) -> Node {
+        let mut channels = levels.to_vec();
+        channels.resize(512, 0);
+        Node::Input {
+            channels: channels.try_into().unwrap(),
+        }
+    }
+
+    fn level(channel: u32, count: u32, value: u8) -> ChannelLevel {
+        ChannelLevel {
+            channel,
+            count,
+            value,
+        }
+    }
+
+    async fn levels(graph: &GraphHandle, id: Uuid) -> Vec<u8> {
+        match graph.get(id).await.unwrap().0 {
+            Node::Input { channels } => channels.into(),
+            node => panic!("{:?} is not an input", node),
+        }
+    }
+
+    #[tokio::test]
+    async fn channel_ranges_are_set() {
+        let (graph, _shutdown) = graph();
+        let id = Uuid::new_v4();
+        graph.insert(id, input(&[])).await.unwrap();
+
+        let ranges = vec![level(1, 2, 10), level(510, 2, 20), level(4, 0, 30)];
+        graph.set_channels(id, ranges).await.unwrap();
+        let channels = levels(&graph, id).await;
+        assert_eq!(channels[..5], [0, 10, 10, 0, 0]);
+        assert_eq!(channels[509..], [0, 20, 20]);
+
+        let past = graph.set_channels(id, vec![level(0, 1, 5), level(511, 2, 5)]);
+        match past.await {
+            Err(Error::Insert(SceneError::Channel { channel, count })) => {
+                assert_eq!((channel, count), (511, 2));
+            }
+            result => panic!("expected a channel error, got {:?}", result),
+        }
+        let overflow = graph.set_channels(id, vec![level(u32::MAX, 2, 5)]).await;
+        assert!(matches!(
+            overflow,
+            Err(Error::Insert(SceneError::Channel { .. }))
+        ));
+        // nothing is set when any range is refused
+        assert_eq!(levels(&graph, id).await, channels);
+    }
+
+    #[tokio::test]
+    async fn channel_runs_are_undone_together() {
+        let (graph, _shutdown) = graph();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        graph.insert_checked(a, input(&[]), None).await.unwrap();
+        graph.insert_checked(b, input(&[]), None).await.unwrap();
+
+        for value in 1..=150 {
+            graph
+                .set_channels(a, vec![level(0, 1, value)])
+                .await
+                .unwrap();
+        }
+        graph.set_channels(b, vec![level(0, 1, 7)]).await.unwrap();
+        graph.set_channels(a, vec![level(1, 1, 9)]).await.unwrap();
+
+        graph.undo().await.unwrap();
+        assert_eq!(levels(&graph, a).await[..2], [150, 0]);
+        graph.undo().await.unwrap();
+        assert_eq!(levels(&graph, b).await[0], 0);
+        graph.undo().await.unwrap();
+        assert_eq!(levels(&graph, a).await[0], 0);
+        graph.redo().await.unwrap();
+        assert_eq!(levels(&graph, a).await[0], 150);
+
+        // an undo ends the run, so the next drag is its own step
+        graph.undo().await.unwrap();
+        graph.set_channels(a, vec![level(0, 1, 40)]).await.unwrap();
+        graph.set_channels(a, vec![level(0, 1, 50)]).await.unwrap();
+        graph.undo().await.unwrap();
+        assert_eq!(levels(&graph, a).await[0], 0);
+        graph.undo().await.unwrap();
+        assert!(graph.get(b).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn batch_recalls_are_undone_together() {
+        let (graph, _shutdown) = graph();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let sum = |a, b| Node::Add {
+            a: Some(a),
+            b: Some(b),
+        };
+        graph.insert_checked(a, input(&[1]), None).await.unwrap();
+        graph.insert_checked(b, input(&[2]), None).await.unwrap();
+        graph.insert_checked(c, sum(a, b), None).await.unwrap();
+
+        // c is listed before what it reads from, and b is pruned
+        let scene = vec![
+            (
+                c,
+                Node::Add {
+                    a: Some(a),
+                    b: None,
+                },
+            ),
+            (a, input(&[3])),
+        ];
+        let held = vec![(a, input(&[1]))];
+        graph.insert_batch_checked(scene, held, true).await.unwrap();
+        assert!(graph.get(b).await.is_err());
+        assert_eq!(levels(&graph, a).await[0], 1);
+
+        graph.undo().await.unwrap();
+        assert_eq!(levels(&graph, b).await[0], 2);
+        assert_eq!(graph.get(c).await.unwrap().0, sum(a, b));
+
+        // redo applies what the batch recorded, not what was held
+        graph.redo().await.unwrap();
+        assert!(graph.get(b).await.is_err());
+        assert_eq!(levels(&graph, a).await[0], 3);
+        assert_eq!(
+            graph.get(c).await.unwrap().0,
+            Node::Add {
+                a: Some(a),
+                b: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn unjournaled_edits_are_not_undone() {
+        let (graph, _shutdown) = graph();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        graph.insert_checked(a, input(&[1]), None).await.unwrap();
+        graph.insert(b, input(&[2])).await.unwrap();
+        graph.insert_batch(vec![(a, input(&[3]))]).await.unwrap();
+
+        graph.undo().await.unwrap();
+        assert!(graph.get(a).await.is_err());
+        assert_eq!(levels(&graph, b).await[0], 2);
+        assert!(matches!(graph.undo().await, Err(Error::EmptyHistory)));
+
+        // a new edit clears what could be redone
+        graph.insert_checked(b, input(&[4]), None).await.unwrap();
+        assert!(matches!(graph.redo().await, Err(Error::EmptyHistory)));
+    }
 }
@@ -12,7 +12,15 @@ pub enum Error {
     NoInput(u32),
 }
 
-#[derive(Clone, Debug)]
+// a level for `count` input channels starting at `channel`, counting from 0
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelLevel {
+    pub channel: u32,
+    pub count: u32,
+    pub value: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Node {
     Input {
         channels: DmxBuffer,
@@ -19,8 +19,14 @@ pub enum Error {
 
 #[derive(Clone, Debug)]
 pub enum GraphUpdate {
-    Update { id: Uuid, channels: DmxBuffer },
-    Closed { id: Uuid },
+    Update {
+        id: Uuid,
+        channels: DmxBuffer,
+        revision: u64,
+    },
+    Closed {
+        id: Uuid,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -29,6 +35,7 @@ pub struct Subscription {
     pub input: Uuid,
     pub index: Index,
     data: DmxBuffer,
+    revision: u64,
     channel: mpsc::Sender<GraphUpdate>,
 }
 
@@ -38,6 +45,7 @@ impl Subscription {
         input: Uuid,
         index: Index,
         states: &HashMap<Uuid, DmxBuffer>,
+        revisions: &HashMap<Uuid, u64>,
         channel: mpsc::Sender<GraphUpdate>,
     ) -> Result<Self, Error> {
         if let Some(data) = states.get(&input) {
@@ -46,6 +54,7 @@ impl Subscription {
                 input,
                 index,
                 data: data.clone(),
+                revision: revisions.get(&input).copied().unwrap_or_default(),
                 channel,
             };
 
@@ -57,13 +66,20 @@ impl Subscription {
         }
     }
 
-    pub async fn update<S>(&mut self, states: &S) -> Result<(), Error>
+    pub async fn update<S>(
+        &mut self,
+        states: &S,
+        revisions: &HashMap<Uuid, u64>,
+    ) -> Result<(), Error>
     where
         S: MapLike<Uuid, DmxBuffer>,
     {
         if let Some(updated) = states.get(&self.input) {
-            if updated != &self.data {
+            // an edit that leaves the output alone still moves the revision
+            let revision = revisions.get(&self.input).copied().unwrap_or_default();
+            if updated != &self.data || revision != self.revision {
                 self.data = updated.clone();
+                self.revision = revision;
                 self.send_state().await?;
             }
 
@@ -82,6 +98,7 @@ impl Subscription {
             .send(GraphUpdate::Update {
                 id: self.id,
                 channels: self.data.clone(),
+                revision: self.revision,
             })
             .await
             .map_err(|_| {
@@ -0,0 +1,391 @@
+use std::time::Duration;
+
+use crate::Error;
+
+use ola::DmxBuffer;
+use uuid::Uuid;
+
+pub const MAGIC: &[u8; 8] = b"CBMIXREC";
+pub const VERSION: u16 = 1;
+
+const FRAME_FULL: u8 = 0;
+const FRAME_SPARSE: u8 = 1;
+
+// a sparse frame costs three bytes per changed channel, so past this many
+// changes a full frame is smaller
+const SPARSE_LIMIT: usize = 170;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stream {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub streams: Vec<Stream>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Full(Box<DmxBuffer>),
+    Sparse(Vec<(u16, u8)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub time: Duration,
+    pub stream: u16,
+    pub frame: Frame,
+}
+
+impl Header {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&(self.streams.len() as u16).to_le_bytes());
+        for stream in &self.streams {
+            buf.extend_from_slice(stream.id.as_bytes());
+            buf.extend_from_slice(&(stream.name.len() as u16).to_le_bytes());
+            buf.extend_from_slice(stream.name.as_bytes());
+        }
+    }
+
+    pub fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        if take(buf, MAGIC.len())? != MAGIC {
+            return Err(Error::Magic);
+        }
+
+        let version = read_u16(buf)?;
+        if version != VERSION {
+            return Err(Error::Version(version));
+        }
+
+        let count = read_u16(buf)?;
+        let mut streams = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let id = Uuid::from_slice(take(buf, 16)?).map_err(|_| Error::Truncated)?;
+            let len = read_u16(buf)?;
+            let name =
+                String::from_utf8(take(buf, len as usize)?.to_vec()).map_err(|_| Error::Corrupt)?;
+            streams.push(Stream { id, name });
+        }
+
+        Ok(Self { streams })
+    }
+}
+
+impl Frame {
+    // the smallest frame that turns `previous` into `next`
+    pub fn diff(previous: Option<&DmxBuffer>, next: &DmxBuffer) -> Self {
+        if let Some(previous) = previous {
+            let changes = previous
+                .iter()
+                .zip(next.iter())
+                .enumerate()
+                .filter(|(_, (a, b))| a != b)
+                .map(|(i, (_, b))| (i as u16, *b))
+                .collect::<Vec<(u16, u8)>>();
+
+            if changes.len() <= SPARSE_LIMIT {
+                return Frame::Sparse(changes);
+            }
+        }
+
+        Frame::Full(Box::new(next.clone()))
+    }
+
+    pub fn apply(&self, buffer: &mut DmxBuffer) {
+        match self {
+            Frame::Full(channels) => *buffer = (**channels).clone(),
+            Frame::Sparse(changes) => {
+                for (channel, value) in changes {
+                    buffer[*channel as usize] = *value;
+                }
+            }
+        }
+    }
+}
+
+impl Record {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.time.as_micros() as u64).to_le_bytes());
+        buf.extend_from_slice(&self.stream.to_le_bytes());
+        match &self.frame {
+            Frame::Full(channels) => {
+                buf.push(FRAME_FULL);
+                buf.extend(channels.iter());
+            }
+            Frame::Sparse(changes) => {
+                buf.push(FRAME_SPARSE);
+                buf.extend_from_slice(&(changes.len() as u16).to_le_bytes());
+                for (channel, value) in changes {
+                    buf.extend_from_slice(&channel.to_le_bytes());
+                    buf.push(*value);
+                }
+            }
+        }
+    }
+
+    pub fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        let time = Duration::from_micros(u64::from_le_bytes(
+            take(buf, 8)?.try_into().expect("convert slice to u64"),
+        ));
+        let stream = read_u16(buf)?;
+        let frame = match take(buf, 1)?[0] {
+            FRAME_FULL => Frame::Full(Box::new(
+                take(buf, 512)?
+                    .to_vec()
+                    .try_into()
+                    .map_err(|_| Error::Corrupt)?,
+            )),
+            FRAME_SPARSE => {
+                let count = read_u16(buf)?;
+                let mut changes = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let channel = read_u16(buf)?;
+                    if channel >= 512 {
+                        return Err(Error::Corrupt);
+                    }
+                    changes.push((channel, take(buf, 1)?[0]));
+                }
+
+                Frame::Sparse(changes)
+            }
+            _ => return Err(Error::Corrupt),
+        };
+
+        Ok(Self {
+            time,
+            stream,
+            frame,
+        })
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if buf.len() < len {
+        return Err(Error::Truncated);
+    }
+
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+
+    Ok(head)
+}
+
+fn read_u16(buf: &mut &[u8]) -> Result<u16, Error> {
+    Ok(u16::from_le_bytes(
+        take(buf, 2)?.try_into().expect("convert slice to u16"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header {
+            streams: vec![
+                Stream {
+                    id: Uuid::new_v4(),
+                    name: "desk".to_string(),
+                },
+                Stream {
+                    id: Uuid::new_v4(),
+                    name: "bühne".to_string(),
+                },
+            ],
+        }
+    }
+
+    fn universe(level: u8) -> DmxBuffer {
+        vec![level; 512].try_into().unwrap()
+    }
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                time: Duration::ZERO,
+                stream: 0,
+                frame: Frame::Full(Box::new(universe(10))),
+            },
+            Record {
+                time: Duration::from_micros(1_500_250),
+                stream: 1,
+                frame: Frame::Sparse(vec![(0, 255), (511, 1)]),
+            },
+            Record {
+                time: Duration::from_secs(3600),
+                stream: 0,
+                frame: Frame::Sparse(Vec::new()),
+            },
+        ]
+    }
+
+    fn encode_all(header: &Header, records: &[Record]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        header.encode(&mut buf);
+        for record in records {
+            record.encode(&mut buf);
+        }
+
+        buf
+    }
+
+    #[test]
+    fn round_trip() {
+        let (header, records) = (header(), records());
+        let file = encode_all(&header, &records);
+
+        let mut buf = &file[..];
+        assert_eq!(Header::decode(&mut buf).unwrap(), header);
+        for record in &records {
+            assert_eq!(&Record::decode(&mut buf).unwrap(), record);
+        }
+        assert!(buf.is_empty());
+    }
+
+    // the layout is what other versions read, so it's pinned down byte for
+    // byte rather than only checked against itself
+    #[test]
+    fn layout() {
+        let id = Uuid::from_u128(0x0102030405060708090a0b0c0d0e0f10);
+        let mut buf = Vec::new();
+        Header {
+            streams: vec![Stream {
+                id,
+                name: "a".to_string(),
+            }],
+        }
+        .encode(&mut buf);
+        Record {
+            time: Duration::from_micros(0x0102),
+            stream: 3,
+            frame: Frame::Sparse(vec![(0x0105, 7)]),
+        }
+        .encode(&mut buf);
+
+        let mut expected = b"CBMIXREC".to_vec();
+        expected.extend([1, 0, 1, 0]);
+        expected.extend(id.as_bytes());
+        expected.extend([1, 0, b'a']);
+        expected.extend([
+            0x02,
+            0x01,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            3,
+            0,
+            FRAME_SPARSE,
+            1,
+            0,
+            0x05,
+            0x01,
+            7,
+        ]);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let mut file = Vec::new();
+        header().encode(&mut file);
+
+        for len in 0..file.len() {
+            let result = Header::decode(&mut &file[..len]);
+            assert!(
+                matches!(result, Err(Error::Truncated)),
+                "header cut to {} bytes gave {:?}",
+                len,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_records() {
+        for record in records() {
+            let mut file = Vec::new();
+            record.encode(&mut file);
+
+            for len in 0..file.len() {
+                let result = Record::decode(&mut &file[..len]);
+                assert!(
+                    matches!(result, Err(Error::Truncated)),
+                    "record cut to {} bytes gave {:?}",
+                    len,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut file = encode_all(&header(), &[]);
+        file[0] = b'X';
+        assert!(matches!(Header::decode(&mut &file[..]), Err(Error::Magic)));
+
+        let mut file = encode_all(&header(), &[]);
+        file[8..10].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(
+            Header::decode(&mut &file[..]),
+            Err(Error::Version(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupt_records() {
+        let mut file = Vec::new();
+        Record {
+            time: Duration::ZERO,
+            stream: 0,
+            frame: Frame::Sparse(vec![(511, 1)]),
+        }
+        .encode(&mut file);
+
+        let mut kind = file.clone();
+        kind[10] = 2;
+        assert!(matches!(
+            Record::decode(&mut &kind[..]),
+            Err(Error::Corrupt)
+        ));
+
+        let mut channel = file.clone();
+        channel[13..15].copy_from_slice(&512u16.to_le_bytes());
+        assert!(matches!(
+            Record::decode(&mut &channel[..]),
+            Err(Error::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn diff_rebuilds_next_frame() {
+        let previous = universe(0);
+        let mut few = universe(0);
+        few[3] = 200;
+        few[511] = 1;
+        let many = universe(50);
+
+        assert_eq!(Frame::diff(None, &few), Frame::Full(Box::new(few.clone())));
+        assert_eq!(
+            Frame::diff(Some(&previous), &few),
+            Frame::Sparse(vec![(3, 200), (511, 1)])
+        );
+        assert!(matches!(
+            Frame::diff(Some(&previous), &many),
+            Frame::Full(_)
+        ));
+
+        for next in [few, many] {
+            let mut channels = previous.clone();
+            Frame::diff(Some(&previous), &next).apply(&mut channels);
+            assert_eq!(channels, next);
+        }
+    }
+}
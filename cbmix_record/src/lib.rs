@@ -0,0 +1,31 @@
+pub mod format;
+mod player;
+pub mod players;
+mod recorder;
+
+pub use player::{PlaybackOptions, Player, PlayerHandle};
+pub use recorder::Recorder;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Unable to access recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("File is not a cbmix recording")]
+    Magic,
+    #[error("Unsupported recording version {0}")]
+    Version(u16),
+    #[error("Recording ended unexpectedly")]
+    Truncated,
+    #[error("Recording is corrupt")]
+    Corrupt,
+    #[error("Recording has no stream named {0}")]
+    UnknownStream(String),
+    #[error("Playback speed must be positive, got {0}")]
+    Speed(f64),
+    #[error("Failed to subscribe to graph")]
+    Subscribe,
+    #[error("Player is no longer running")]
+    PlayerStopped,
+}
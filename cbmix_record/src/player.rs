@@ -0,0 +1,339 @@
+use std::future::pending;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::format::{Frame, Header, Record};
+use crate::Error;
+
+use cbmix_common::shutdown;
+use cbmix_graph::{GraphHandle, Node};
+use ola::DmxBuffer;
+use tokio::{
+    fs::read,
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+const COMMAND_BUFFER_SIZE: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct PlaybackOptions {
+    pub looping: bool,
+    pub speed: f64,
+    pub start: Duration,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            looping: false,
+            speed: 1.0,
+            start: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Seek(Duration),
+    SetSpeed(f64),
+    SetLooping(bool),
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerHandle {
+    commands: mpsc::Sender<Command>,
+}
+
+pub struct Player {
+    graph: GraphHandle,
+    id: Uuid,
+    frames: Vec<(Duration, Frame)>,
+    options: PlaybackOptions,
+    commands_tx: mpsc::Sender<Command>,
+    commands_rx: mpsc::Receiver<Command>,
+    shutdown: shutdown::Receiver,
+}
+
+// where playback is in the recording, and when it got there
+struct Position {
+    index: usize,
+    channels: DmxBuffer,
+    anchor: (Instant, Duration),
+}
+
+impl Player {
+    // load a stream from a recording. the first stream is used if none is
+    // named
+    pub async fn open(
+        path: &Path,
+        stream: Option<&str>,
+        id: Uuid,
+        options: PlaybackOptions,
+        graph: GraphHandle,
+        shutdown: shutdown::Receiver,
+    ) -> Result<Self, Error> {
+        if !(options.speed.is_finite() && options.speed > 0.0) {
+            return Err(Error::Speed(options.speed));
+        }
+
+        let file = read(path).await?;
+        let mut buf = &file[..];
+
+        let header = Header::decode(&mut buf)?;
+        let index = match stream {
+            Some(name) => header
+                .streams
+                .iter()
+                .position(|s| s.name == name)
+                .ok_or_else(|| Error::UnknownStream(name.to_string()))?,
+            None if !header.streams.is_empty() => 0,
+            None => return Err(Error::UnknownStream("(any)".to_string())),
+        } as u16;
+
+        let mut frames = Vec::new();
+        while !buf.is_empty() {
+            match Record::decode(&mut buf) {
+                Ok(record) if record.stream == index => frames.push((record.time, record.frame)),
+                Ok(_) => {}
+                Err(Error::Truncated) => {
+                    // recordings cut off by a crash end partway through a record
+                    warn!("recording {} ends early", path.display());
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        debug!("loaded {} frames from {}", frames.len(), path.display());
+
+        let (commands_tx, commands_rx) = mpsc::channel(COMMAND_BUFFER_SIZE);
+
+        Ok(Self {
+            graph,
+            id,
+            frames,
+            options,
+            commands_tx,
+            commands_rx,
+            shutdown,
+        })
+    }
+
+    pub fn handle(&self) -> PlayerHandle {
+        PlayerHandle {
+            commands: self.commands_tx.clone(),
+        }
+    }
+
+    pub async fn serve(mut self) {
+        let mut position = self.seek(self.options.start);
+        self.send(&position.channels).await;
+
+        loop {
+            let deadline = self.deadline(&position);
+
+            tokio::select! {
+                _ = next_frame(deadline) => {
+                    let (_, frame) = &self.frames[position.index];
+                    frame.apply(&mut position.channels);
+                    position.index += 1;
+                    self.send(&position.channels).await;
+
+                    if position.index == self.frames.len() && self.options.looping {
+                        position = self.seek(Duration::ZERO);
+                        self.send(&position.channels).await;
+                    }
+                }
+                command = self.commands_rx.recv() => match command {
+                    Some(Command::Seek(time)) => {
+                        position = self.seek(time);
+                        self.send(&position.channels).await;
+                    }
+                    Some(Command::SetSpeed(speed)) => {
+                        // keep the current spot in the recording when changing pace
+                        let now = Instant::now();
+                        position.anchor = (now, self.elapsed(&position, now));
+                        self.options.speed = speed;
+                    }
+                    Some(Command::SetLooping(looping)) => self.options.looping = looping,
+                    None => break,
+                },
+                _ = self.shutdown.recv() => break,
+            }
+        }
+    }
+
+    // rebuild the frame at a point in the recording from the frames before it
+    fn seek(&self, time: Duration) -> Position {
+        let mut channels = DmxBuffer::new();
+        let mut index = 0;
+        while let Some((frame_time, frame)) = self.frames.get(index) {
+            if *frame_time > time {
+                break;
+            }
+            frame.apply(&mut channels);
+            index += 1;
+        }
+
+        Position {
+            index,
+            channels,
+            anchor: (Instant::now(), time),
+        }
+    }
+
+    fn elapsed(&self, position: &Position, now: Instant) -> Duration {
+        let (instant, time) = position.anchor;
+        time + now.duration_since(instant).mul_f64(self.options.speed)
+    }
+
+    fn deadline(&self, position: &Position) -> Option<Instant> {
+        let (frame_time, _) = self.frames.get(position.index)?;
+        let (instant, time) = position.anchor;
+
+        Some(instant + frame_time.saturating_sub(time).div_f64(self.options.speed))
+    }
+
+    async fn send(&self, channels: &DmxBuffer) {
+        let node = Node::Input {
+            channels: channels.clone(),
+        };
+
+        if let Err(e) = self.graph.insert(self.id, node).await {
+            error!("failed to send playback frame to graph: {}", e);
+        }
+    }
+}
+
+impl PlayerHandle {
+    pub async fn seek(&self, time: Duration) -> Result<(), Error> {
+        self.send(Command::Seek(time)).await
+    }
+
+    pub async fn set_speed(&self, speed: f64) -> Result<(), Error> {
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(Error::Speed(speed));
+        }
+
+        self.send(Command::SetSpeed(speed)).await
+    }
+
+    pub async fn set_looping(&self, looping: bool) -> Result<(), Error> {
+        self.send(Command::SetLooping(looping)).await
+    }
+
+    async fn send(&self, command: Command) -> Result<(), Error> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| Error::PlayerStopped)
+    }
+}
+
+async fn next_frame(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cbmix_graph::Graph;
+    use tokio::time::sleep;
+
+    // a player of `frames` (seconds and the level of every channel), with the
+    // graph it plays into
+    fn player(frames: &[(u64, u8)], shutdown: &shutdown::Sender) -> (Player, GraphHandle) {
+        let graph = Graph::new(shutdown.subscribe());
+        let handle = graph.handle();
+        tokio::spawn(graph.serve());
+
+        let frames = frames
+            .iter()
+            .map(|(seconds, level)| {
+                let channels = vec![*level; 512].try_into().unwrap();
+                (
+                    Duration::from_secs(*seconds),
+                    Frame::Full(Box::new(channels)),
+                )
+            })
+            .collect();
+        let (commands_tx, commands_rx) = mpsc::channel(COMMAND_BUFFER_SIZE);
+        let player = Player {
+            graph: handle.clone(),
+            id: Uuid::new_v4(),
+            frames,
+            options: PlaybackOptions::default(),
+            commands_tx,
+            commands_rx,
+            shutdown: shutdown.subscribe(),
+        };
+
+        (player, handle)
+    }
+
+    // the clock is paused, so sleeping also lets the player catch up
+    async fn level_after(graph: &GraphHandle, id: Uuid, millis: u64) -> u8 {
+        sleep(Duration::from_millis(millis)).await;
+        match graph.get(id).await.unwrap() {
+            (Node::Input { channels }, _) => Vec::from(channels)[0],
+            (node, _) => panic!("expected an input node, got {:?}", node),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frames_play_on_time() {
+        let shutdown = shutdown::Sender::new();
+        let (player, graph) = player(&[(0, 10), (1, 20), (2, 30)], &shutdown);
+        let id = player.id;
+        tokio::spawn(player.serve());
+
+        assert_eq!(level_after(&graph, id, 500).await, 10);
+        assert_eq!(level_after(&graph, id, 1000).await, 20);
+        assert_eq!(level_after(&graph, id, 1000).await, 30);
+        // without looping, the last frame holds
+        assert_eq!(level_after(&graph, id, 5000).await, 30);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeks_and_speed_move_playback() {
+        let shutdown = shutdown::Sender::new();
+        let (player, graph) = player(&[(0, 10), (1, 20), (2, 30)], &shutdown);
+        let (id, handle) = (player.id, player.handle());
+        tokio::spawn(player.serve());
+
+        assert_eq!(level_after(&graph, id, 1500).await, 20);
+        handle.seek(Duration::from_millis(500)).await.unwrap();
+        assert_eq!(level_after(&graph, id, 1).await, 10);
+        assert_eq!(level_after(&graph, id, 600).await, 20);
+
+        // 1.1s in, so double speed reaches the 2s frame in under half a second
+        handle.set_speed(2.0).await.unwrap();
+        assert_eq!(level_after(&graph, id, 400).await, 20);
+        assert_eq!(level_after(&graph, id, 100).await, 30);
+        assert!(matches!(handle.set_speed(0.0).await, Err(Error::Speed(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn loops_restart_from_the_top() {
+        let shutdown = shutdown::Sender::new();
+        // a long hold at the start, which shouldn't delay the restart
+        let (player, graph) = player(&[(0, 10), (10, 20), (12, 30)], &shutdown);
+        let (id, handle) = (player.id, player.handle());
+        tokio::spawn(player.serve());
+
+        handle.set_looping(true).await.unwrap();
+        handle.seek(Duration::from_secs(11)).await.unwrap();
+        assert_eq!(level_after(&graph, id, 500).await, 20);
+        assert_eq!(level_after(&graph, id, 1000).await, 10);
+        assert_eq!(level_after(&graph, id, 10000).await, 20);
+
+        handle.set_looping(false).await.unwrap();
+        assert_eq!(level_after(&graph, id, 3000).await, 30);
+    }
+}
@@ -0,0 +1,16 @@
+use std::collections::HashMap;
+
+use crate::PlayerHandle;
+
+use tokio::sync::watch;
+use uuid::Uuid;
+
+// handles to the running players, keyed by the id of the node each one drives
+pub type PlayerHandles = HashMap<Uuid, PlayerHandle>;
+
+pub type Sender = watch::Sender<PlayerHandles>;
+pub type Receiver = watch::Receiver<PlayerHandles>;
+
+pub fn channel() -> (Sender, Receiver) {
+    watch::channel(HashMap::new())
+}
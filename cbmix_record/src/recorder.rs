@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::format::{Frame, Header, Record, Stream};
+use crate::Error;
+
+use cbmix_common::shutdown;
+use cbmix_graph::{GraphHandle, GraphUpdate};
+use ola::DmxBuffer;
+use tokio::{
+    fs::{create_dir_all, File},
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+    time::{interval, Instant},
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+const INCOMING_BUFFER_SIZE: usize = 30;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Recorder {
+    graph: GraphHandle,
+    directory: PathBuf,
+    nodes: Vec<(String, Uuid)>,
+    shutdown: shutdown::Receiver,
+}
+
+struct Recording {
+    file: BufWriter<File>,
+    start: Instant,
+    streams: HashMap<Uuid, (u16, Option<DmxBuffer>)>,
+    buf: Vec<u8>,
+}
+
+impl Recorder {
+    pub fn new(
+        directory: PathBuf,
+        nodes: Vec<(String, Uuid)>,
+        graph: GraphHandle,
+        shutdown: shutdown::Receiver,
+    ) -> Self {
+        Self {
+            graph,
+            directory,
+            nodes,
+            shutdown,
+        }
+    }
+
+    pub async fn serve(mut self) {
+        // a broken recording shouldn't take the rest of the show down with it
+        if let Err(e) = self.record().await {
+            error!("recording stopped: {}", e);
+        }
+    }
+
+    async fn record(&mut self) -> Result<(), Error> {
+        let (subscriber, mut updates) = mpsc::channel(INCOMING_BUFFER_SIZE);
+
+        create_dir_all(&self.directory).await?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = self.directory.join(format!("cbmix-{}.cbrec", started));
+        let file = BufWriter::new(File::create(&path).await?);
+        info!("recording to {}", path.display());
+
+        let mut recording = Recording {
+            file,
+            start: Instant::now(),
+            streams: HashMap::new(),
+            buf: Vec::new(),
+        };
+
+        Header {
+            streams: self
+                .nodes
+                .iter()
+                .map(|(name, id)| Stream {
+                    id: *id,
+                    name: name.clone(),
+                })
+                .collect(),
+        }
+        .encode(&mut recording.buf);
+        recording.file.write_all(&recording.buf).await?;
+        recording.buf.clear();
+
+        for (index, (name, id)) in self.nodes.iter().enumerate() {
+            let subscription = self
+                .graph
+                .subscribe(*id, subscriber.clone())
+                .await
+                .map_err(|e| {
+                    error!("failed to subscribe to {}: {}", name, e);
+                    Error::Subscribe
+                })?;
+            recording.streams.insert(subscription, (index as u16, None));
+
+            // handle the first update right away so subscribing to lots of
+            // nodes doesn't fill up the channel
+            if let Some(update) = updates.recv().await {
+                recording.handle_update(update).await?;
+            }
+        }
+
+        let mut flush = interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                update = updates.recv() => match update {
+                    Some(update) => recording.handle_update(update).await?,
+                    None => break,
+                },
+                _ = flush.tick() => recording.file.flush().await?,
+                _ = self.shutdown.recv() => break,
+            }
+        }
+
+        recording.file.flush().await?;
+
+        Ok(())
+    }
+}
+
+impl Recording {
+    async fn handle_update(&mut self, update: GraphUpdate) -> Result<(), Error> {
+        match update {
+            GraphUpdate::Update { id, channels, .. } => {
+                if let Some((stream, previous)) = self.streams.get_mut(&id) {
+                    // an edit can move the revision without changing levels
+                    if previous.as_ref() == Some(&channels) {
+                        return Ok(());
+                    }
+
+                    Record {
+                        time: self.start.elapsed(),
+                        stream: *stream,
+                        frame: Frame::diff(previous.as_ref(), &channels),
+                    }
+                    .encode(&mut self.buf);
+                    *previous = Some(channels);
+
+                    self.file.write_all(&self.buf).await?;
+                    self.buf.clear();
+                } else {
+                    warn!("recieved update from unknown recording {}", id);
+                }
+            }
+            GraphUpdate::Closed { id } => {
+                warn!("recorded node for subscription {} was removed", id);
+                self.streams.remove(&id);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,451 @@
+mod names;
+
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::io::{read_to_string as read_all, stdin, stdout, IsTerminal, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process::exit;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use names::{resolve, Names};
+
+use anyhow::{anyhow, bail, Context, Error};
+use cbmix_admin_proto::{node::Body, node_from_proto, node_to_proto, Nodes};
+use cbmix_client::Client;
+use cbmix_graph::{ChannelLevel, Node};
+use clap::{ArgGroup, Parser, Subcommand};
+use directories::ProjectDirs;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+const DEFAULT_URL: &str = "ws://localhost:8080/api/ws";
+
+/// Control a running cbmix instance over its admin protocol
+///
+/// Nodes are given by id or by their name in the cbmix config.
+#[derive(Parser)]
+struct Args {
+    /// Admin WebSocket endpoint
+    #[arg(long, env = "CBMIX_URL", default_value = DEFAULT_URL)]
+    url: String,
+    /// Admin token, if the server requires one
+    #[arg(long, env = "CBMIX_TOKEN")]
+    token: Option<String>,
+    /// Config file to read node names from [default: the cbmix config]
+    #[arg(long, env = "CBMIX_CONFIG")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List every node in the graph
+    List,
+    /// Print a node as JSON
+    Get { node: String },
+    /// Replace a node with JSON from the command line or stdin, failing if the
+    /// JSON has a revision and the node has changed since
+    Set { node: String, json: Option<String> },
+    /// Set channels of an input node, like 1=255 or 10-20=0
+    Channels {
+        node: String,
+        #[arg(required = true)]
+        levels: Vec<Level>,
+    },
+    /// Remove nodes from the graph
+    Remove {
+        #[arg(required = true)]
+        nodes: Vec<String>,
+    },
+    /// Undo the last edit made over the admin protocol
+    Undo,
+    /// Redo the last undone edit
+    Redo,
+    /// Save, list, and recall snapshots of the graph
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+    /// Seek, change the speed of, or loop a playback node
+    #[command(group(ArgGroup::new("change").required(true).multiple(true)))]
+    Playback {
+        node: String,
+        /// Jump to this many seconds into the recording
+        #[arg(long, group = "change")]
+        seek: Option<f64>,
+        /// Play at this multiple of the recorded speed
+        #[arg(long, group = "change")]
+        speed: Option<f64>,
+        /// Start over at the end of the recording
+        #[arg(long = "loop", group = "change")]
+        looping: Option<bool>,
+    },
+    /// Print the current levels of a node, or of every node
+    State { node: Option<String> },
+    /// Print a node's levels as they change
+    Watch { node: String },
+    /// Print the whole graph as JSON
+    Dump,
+    /// Insert every node from a dump in a file or stdin
+    Load { file: Option<PathBuf> },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Save the levels of every input node under a name
+    Save {
+        name: String,
+        /// Save every node, not just inputs
+        #[arg(long)]
+        all: bool,
+    },
+    /// List saved snapshots
+    List,
+    /// Bring back a saved snapshot
+    Recall {
+        name: String,
+        /// Crossfade input levels over this many seconds
+        #[arg(long)]
+        fade: Option<f32>,
+    },
+    /// Delete a saved snapshot
+    Delete { name: String },
+}
+
+// channels are numbered from 1, like on a console
+#[derive(Clone, Debug)]
+struct Level {
+    channels: RangeInclusive<usize>,
+    value: u8,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = Args::parse();
+
+    if let Err(e) = run(args).await {
+        eprintln!("{:#}", e);
+        exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<(), Error> {
+    let config = match args.config {
+        Some(config) => config,
+        None => ProjectDirs::from("", "", "cbmix")
+            .context("determine program directories")?
+            .config_dir()
+            .join("config.toml"),
+    };
+    let names = Names::load(&config)?;
+
+    let client = Client::connect(&args.url, args.token.as_deref())
+        .await
+        .with_context(|| format!("connecting to {}", args.url))?;
+
+    match args.command {
+        Command::List => {
+            let mut nodes = client.list().await?;
+            nodes.sort_by_key(|(id, _, _)| names.display(id));
+
+            for (id, node, _) in nodes {
+                println!(
+                    "{}  {:<24}  {}",
+                    id,
+                    names.get(&id).unwrap_or("-"),
+                    describe(&node, &names)
+                );
+            }
+        }
+        Command::Get { node } => {
+            let id = resolve(&node);
+            let (node, revision) = client.get(id).await?;
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&node_to_proto(&id, &node, Some(revision)))?
+            );
+        }
+        Command::Set { node: target, json } => {
+            let json = match json {
+                Some(json) => json,
+                None => read_all(stdin())?,
+            };
+            let mut node: cbmix_admin_proto::Node =
+                serde_json::from_str(&json).map_err(|e| anyhow!("invalid node JSON: {}", e))?;
+            node.id = None;
+            let revision = node.revision;
+
+            let (_, body) = parse_node(node)?;
+            client
+                .insert_checked(resolve(&target), body, revision)
+                .await?;
+        }
+        Command::Channels { node, levels } => {
+            let levels = levels
+                .into_iter()
+                .map(|level| ChannelLevel {
+                    channel: *level.channels.start() as u32 - 1,
+                    count: level.channels.count() as u32,
+                    value: level.value,
+                })
+                .collect();
+            client.set_channels(resolve(&node), levels).await?;
+        }
+        Command::Remove { nodes } => {
+            for node in nodes {
+                client.remove(resolve(&node)).await?;
+            }
+        }
+        Command::Undo => client.undo().await?,
+        Command::Redo => client.redo().await?,
+        Command::Snapshot(SnapshotCommand::Save { name, all }) => {
+            let info = client.create_snapshot(&name, all).await?;
+            println!("saved {} with {} nodes", info.name, info.node_count);
+        }
+        Command::Snapshot(SnapshotCommand::List) => {
+            for info in client.list_snapshots().await? {
+                let saved = info.created.map(age).unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{:<24}  {:>10}  {} nodes",
+                    info.name, saved, info.node_count
+                );
+            }
+        }
+        Command::Snapshot(SnapshotCommand::Recall { name, fade }) => {
+            let fade = fade
+                .map(Duration::try_from_secs_f32)
+                .transpose()
+                .map_err(|_| anyhow!("fade must be a positive number of seconds"))?;
+            client.recall_snapshot(&name, fade).await?;
+        }
+        Command::Snapshot(SnapshotCommand::Delete { name }) => {
+            client.delete_snapshot(&name).await?
+        }
+        Command::Playback {
+            node,
+            seek,
+            speed,
+            looping,
+        } => {
+            let seek = seek
+                .map(Duration::try_from_secs_f64)
+                .transpose()
+                .map_err(|_| anyhow!("seek must be a positive number of seconds"))?;
+            client
+                .control_playback(resolve(&node), seek, speed, looping)
+                .await?;
+        }
+        Command::State { node: Some(node) } => {
+            let channels = client.get_state(resolve(&node)).await?;
+            println!("{}", levels(&Vec::from(channels)));
+        }
+        Command::State { node: None } => {
+            let mut states = client.get_states().await?;
+            states.sort_by_key(|(id, _)| names.display(id));
+
+            for (id, channels) in states {
+                println!(
+                    "{:<24}  {}",
+                    names.display(&id),
+                    levels(&Vec::from(channels))
+                );
+            }
+        }
+        Command::Watch { node } => {
+            let mut subscription = client.subscribe(resolve(&node)).await?;
+            let terminal = stdout().is_terminal();
+
+            while let Some(channels) = subscription.next().await {
+                let channels: Vec<u8> = channels.into();
+                if terminal {
+                    draw(&node, &channels)?;
+                } else {
+                    println!("{}", levels(&channels));
+                }
+            }
+        }
+        Command::Dump => {
+            let mut nodes = client.list().await?;
+            nodes.sort_by_key(|(id, _, _)| *id);
+
+            let nodes = Nodes {
+                nodes: nodes
+                    .iter()
+                    .map(|(i, n, r)| node_to_proto(i, n, Some(*r)))
+                    .collect(),
+            };
+            println!("{}", serde_json::to_string_pretty(&nodes)?);
+        }
+        Command::Load { file } => {
+            let json = match file {
+                Some(file) => {
+                    read_to_string(&file).with_context(|| format!("reading {}", file.display()))?
+                }
+                None => read_all(stdin())?,
+            };
+            let nodes: Nodes =
+                serde_json::from_str(&json).map_err(|e| anyhow!("invalid dump JSON: {}", e))?;
+
+            let nodes = nodes
+                .nodes
+                .into_iter()
+                .map(|node| match parse_node(node)? {
+                    (Some(id), node) => Ok((id, node)),
+                    (None, _) => bail!("every node in a dump needs an id"),
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            load(&client, nodes, &names).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// insert nodes after the ones they read from, so the graph can find each
+// node's inputs as it goes
+async fn load(client: &Client, nodes: Vec<(Uuid, Node)>, names: &Names) -> Result<(), Error> {
+    let mut present: HashSet<Uuid> = client
+        .list()
+        .await?
+        .into_iter()
+        .map(|(i, _, _)| i)
+        .collect();
+
+    let mut pending = nodes;
+    while !pending.is_empty() {
+        let (ready, blocked): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(_, node)| {
+            node.dependencies()
+                .iter()
+                .flatten()
+                .all(|dependency| present.contains(dependency))
+        });
+        if ready.is_empty() {
+            let blocked: Vec<String> = blocked.iter().map(|(id, _)| names.display(id)).collect();
+            bail!(
+                "unable to load {}, their inputs are missing or form a cycle",
+                blocked.join(", ")
+            );
+        }
+
+        for (id, node) in ready {
+            client
+                .insert(id, node)
+                .await
+                .with_context(|| format!("loading {}", names.display(&id)))?;
+            present.insert(id);
+        }
+        pending = blocked;
+    }
+
+    Ok(())
+}
+
+// node references in JSON from the user may be names rather than ids
+fn parse_node(mut node: cbmix_admin_proto::Node) -> Result<(Option<Uuid>, Node), Error> {
+    let resolve_ref = |r: &mut Option<String>| {
+        if let Some(name) = r {
+            *name = resolve(name).to_string();
+        }
+    };
+    match &mut node.body {
+        Some(Body::Add(body)) => {
+            resolve_ref(&mut body.a);
+            resolve_ref(&mut body.b);
+        }
+        Some(Body::Multiply(body)) => {
+            resolve_ref(&mut body.a);
+            resolve_ref(&mut body.b);
+        }
+        Some(Body::Rewire(body)) => resolve_ref(&mut body.input),
+        Some(Body::Input(_)) | None => {}
+    }
+    if let Some(id) = &mut node.id {
+        *id = resolve(id).to_string();
+    }
+
+    node_from_proto(&node).ok_or_else(|| {
+        anyhow!("incomplete node, input channels and rewire maps need all 512 entries")
+    })
+}
+
+fn describe(node: &Node, names: &Names) -> String {
+    let name = |id: &Option<Uuid>| match id {
+        Some(id) => names.display(id),
+        None => "-".to_string(),
+    };
+
+    match node {
+        Node::Input { channels } => {
+            let active = channels.iter().filter(|c| **c > 0).count();
+            format!("input     {} channels active", active)
+        }
+        Node::Add { a, b } => format!("add       {} + {}", name(a), name(b)),
+        Node::Multiply { a, b } => format!("multiply  {} * {}", name(a), name(b)),
+        Node::Rewire { input, .. } => format!("rewire    {}", name(input)),
+    }
+}
+
+// how long ago a snapshot was saved, roughly
+fn age(created: u64) -> String {
+    let created = UNIX_EPOCH + Duration::from_millis(created);
+    let seconds = SystemTime::now()
+        .duration_since(created)
+        .unwrap_or_default()
+        .as_secs();
+
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn levels(channels: &[u8]) -> String {
+    let levels: Vec<String> = channels.iter().map(|c| c.to_string()).collect();
+    levels.join(" ")
+}
+
+// redraw the universe in place, 32 channels to a row
+fn draw(node: &str, channels: &[u8]) -> Result<(), Error> {
+    let mut out = stdout().lock();
+    write!(out, "\x1b[H\x1b[2J{}\r\n", node)?;
+    for (row, levels) in channels.chunks(32).enumerate() {
+        write!(out, "{:>3} ", row * 32 + 1)?;
+        for level in levels {
+            write!(out, "{:>4}", level)?;
+        }
+        write!(out, "\r\n")?;
+    }
+
+    Ok(out.flush()?)
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channels, value) = s
+            .split_once('=')
+            .ok_or("expected CHANNEL=VALUE or FIRST-LAST=VALUE")?;
+        let (first, last) = channels.split_once('-').unwrap_or((channels, channels));
+        let channel = |c: &str| match c.trim().parse() {
+            Ok(c @ 1..=512) => Ok(c),
+            _ => Err(format!("{} is not a channel from 1 to 512", c)),
+        };
+        let (first, last) = (channel(first)?, channel(last)?);
+        if first > last {
+            return Err(format!("channel range {} is backwards", channels));
+        }
+
+        Ok(Level {
+            channels: first..=last,
+            value: value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{} is not a level from 0 to 255", value))?,
+        })
+    }
+}
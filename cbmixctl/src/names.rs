@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use cbmix_graph::NAMESPACE_SCENE;
+use uuid::Uuid;
+
+// the server only knows node ids, but those are derived from the keys of the
+// config's input and node tables, so the config tells us what to call them
+#[derive(Default)]
+pub struct Names {
+    names: HashMap<Uuid, String>,
+}
+
+impl Names {
+    pub fn load(file: &Path) -> Result<Self, Error> {
+        if !file.exists() {
+            return Ok(Default::default());
+        }
+
+        let text = read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+        let config: toml::Table =
+            toml::from_str(&text).with_context(|| format!("parsing {}", file.display()))?;
+
+        let names = ["input", "node"]
+            .iter()
+            .filter_map(|table| config.get(*table).and_then(|t| t.as_table()))
+            .flat_map(|table| table.keys())
+            .map(|name| (id(name), name.clone()))
+            .collect();
+
+        Ok(Self { names })
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&str> {
+        self.names.get(id).map(|n| n.as_str())
+    }
+
+    // the name of a node if it has one, otherwise its id
+    pub fn display(&self, id: &Uuid) -> String {
+        self.get(id)
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| id.to_string())
+    }
+}
+
+// nodes can be given on the command line by id or by config name
+pub fn resolve(node: &str) -> Uuid {
+    Uuid::try_parse(node).unwrap_or_else(|_| id(node))
+}
+
+fn id(name: &str) -> Uuid {
+    Uuid::new_v5(&NAMESPACE_SCENE, name.as_bytes())
+}